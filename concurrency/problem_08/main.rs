@@ -14,19 +14,53 @@
 // We are building a system where:
 // 1. Create once: Spawn N workers threads at the start
 // 2. Reuse threads: Same threads handle all jobs (no spawning/destroying overhead)
-// 3. Job queue: Main thread sends jobs through a channel
+// 3. Job queue: Main thread pushes jobs onto a shared queue
 // 4. Workers compete: Each worker grabs the next available job
 // 5. Track progress: Count how many jobs completed (using atomics)
 
-// The main thread will create a channel for sending jobs
+// The main thread will create a shared queue for jobs
 // It will spawn 4 workers threads, which wait for jobs
-// Sends 20 jobs into the channel
+// Pushes 20 jobs onto the queue
 // We will reuse the same 4 threads for all 20 jobs
 
-use std::sync::{mpsc, Arc, Mutex};
-use std::sync::atomic::{AtomicU32, Ordering};
+// Later on, the pool learns to resize itself:
+// - It grows (up to max_threads) when jobs back up in the queue
+// - Workers above min_threads retire after sitting idle for keep_alive
+// - resize(n) changes the size by hand while the pool is running
+
+// And to shut down in different ways:
+// - join() waits for every queued job, however long that takes
+// - shutdown_graceful(timeout) does the same but gives up after timeout and reports what didn't finish
+// - shutdown_now() hands the queued jobs back instead of running them
+// - Dropping the pool behaves like join(), so no worker threads are left running in the background
+
+// And to run jobs that borrow from the caller's stack:
+// - scope() works like std::thread::scope (problem 14), but runs the closures on the pool's workers instead of new threads
+
+// And to report on itself:
+// - metrics() returns a snapshot of queue depth, busy workers, per-worker counts and latency histograms
+// - The snapshot can be printed in the Prometheus text format for scraping
+
+// And to schedule by priority:
+// - execute_with_priority() lets urgent jobs (like health checks) skip ahead of batch work
+// - Jobs that have waited a long time get "aged" forward so low priority work can't starve
+
+// And to give up on jobs that are no longer wanted:
+// - execute_with_token() ties a job to a CancellationToken - cancel the token and the job is skipped if it hasn't started yet
+// - The job gets the token too, so a long job can poll it and stop early
+// - execute_with_deadline() drops a job if its deadline passed while it was sitting in the queue
+// - cancelled_count() and skipped_count() say how many jobs were dropped for each reason
+
+use std::any::Any;
+use std::marker::PhantomData;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // This is a type alias
 // It is used as short-hand for a longer type signature
@@ -50,10 +84,358 @@ type Job = Box<dyn FnOnce() + Send + 'static>;
 // dyn = dynamic dispatch = trait object
 // We need Box<> since different closures have different sizes, compiler doesn't know which closure we will use, Rust requires all types to have a known size
 
+// When a thread panics, the panic carries a "payload" - usually the &str or String passed to panic!()
+// It is a Box<dyn Any + Send> since the payload can be any type
+// This is the same type std::thread::JoinHandle::join() returns in its Err variant
+type PanicPayload = Box<dyn Any + Send + 'static>;
+
+// The same Priority as in general problem 30, with the same ordering: Low < Medium < High < Critical
+// Problem 30 wrote PartialEq/Eq/PartialOrd/Ord out by hand - deriving them gives exactly the same result,
+// since derived ordering on an enum follows the order the variants are declared in
+// Note: std::sync::atomic::Ordering is already imported as Ordering, so cmp::Ordering is imported as CmpOrdering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Priority {
+    // How many levels below Critical this priority is
+    // Used to push a job's place in line back by that many aging steps
+    // `as u32` gives the variant's position in the declaration (Low = 0 ... Critical = 3), the same thing the derived Ord compares,
+    // so adding or reordering a variant changes both orderings together instead of letting them drift apart
+    fn levels_below_critical(self) -> u32 {
+        Priority::Critical as u32 - self as u32
+    }
+}
+
+// A shared "please stop" flag (the AtomicBool problem 7 imports but never got to use)
+// Cloning a token gives another handle to the SAME flag, so the caller keeps one clone and the job gets another
+// Cancelling can't interrupt a job that is already running - Rust has no safe way to stop a thread from the outside
+// So it works in two places:
+// 1. The pool checks the token when it takes a job off the queue, and skips the job if it is cancelled
+// 2. A running job can poll is_cancelled() and return early
+#[derive(Clone, Default)]
+struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    // One token can be shared by many jobs, so this cancels all of them at once
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// A job waiting in the priority queue
+// BinaryHeap can only order by a value that doesn't change while the job sits in the heap
+// So instead of bumping priorities up over time (which would mean re-sorting the heap), we give every job a "virtual start time":
+//     run_at = time it was queued + (levels below Critical * aging step)
+// The job with the earliest run_at goes first
+// - A fresh Critical job goes to the front of everything queued at the same moment
+// - A Low job queued 3 aging steps ago has the same run_at as a Critical job queued now
+// So the longer a job waits, the more higher priority jobs it overtakes - that's the aging
+struct QueuedJob {
+    // Measured from when the pool was created, since Duration is easy to add to and compare
+    run_at: Duration,
+    // Tie breaker so jobs with the same run_at come out in the order they were queued (FIFO)
+    seq: u64,
+    // When the job was actually queued, for the queue wait histogram
+    queued_at: Instant,
+    // Skip the job if this token is cancelled before a worker picks it up
+    token: Option<CancellationToken>,
+    // Skip the job if a worker picks it up after this point
+    deadline: Option<Instant>,
+    job: Job,
+}
+
+// Same idea as Ord for Task in problem 30: we compare on some fields and ignore the rest (the job itself can't be compared)
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for QueuedJob {}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other)) // Delegate to Ord::cmp
+    }
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // BinaryHeap is a max-heap: .pop() returns the GREATEST element
+        // We want the EARLIEST run_at, so we compare other to self (reversed)
+        other
+            .run_at
+            .cmp(&self.run_at)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
 struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>, // the transmitter, which will be main in our case. We will have one sender and multiple receivers (possible through mutex since you can't clone a receiver)
-    completed_count: Arc<AtomicU32>,
+    shared: Arc<Shared>,
+}
+
+// Everything the workers need to share with each other and with the pool
+// We put it all in one struct so each worker only needs one Arc instead of one Arc per field
+// The individual fields don't need their own Arc anymore - the outer Arc<Shared> is what gets cloned
+struct Shared {
+    // The job queue and the pool's sizing information, all behind one lock
+    // They share a lock since "should a worker be spawned/retired?" depends on the queue length and worker counts at the same moment
+    state: Mutex<State>,
+    // Workers sleep on this Condvar while the queue is empty
+    // execute() wakes one of them up, resize() and join() wake all of them
+    available: Condvar,
+    // Notified every time a worker exits, so shutdown_graceful() can wait for live_workers to hit 0
+    exited: Condvar,
+    // How long a worker above min_threads can sit idle before it retires
+    keep_alive: Duration,
+    // How long a job has to wait to move up one priority level
+    aging: Duration,
+    // The point in time that QueuedJob::run_at is measured from
+    started: Instant,
+    completed_count: AtomicU32,
+    // Jobs that panicked instead of returning normally
+    panicked_count: AtomicU32,
+    // Jobs dropped at dequeue time because their token was cancelled
+    cancelled_count: AtomicU32,
+    // Jobs dropped at dequeue time because their deadline had passed
+    skipped_count: AtomicU32,
+    // The workers live in here (instead of directly in ThreadPool) so a dying worker can push its own replacement
+    workers: Mutex<Vec<Worker>>,
+    // Per-worker counters, keyed by worker id
+    // Each worker holds its own Arc and updates it without touching this lock - the lock is only for adding/removing/snapshotting
+    worker_stats: Mutex<HashMap<usize, Arc<WorkerStats>>>,
+    // How long jobs sat in the queue before a worker picked them up
+    queue_wait: Histogram,
+    // How long jobs took to run once they started
+    run_time: Histogram,
+}
+
+impl Shared {
+    // The clock WorkerStats uses, since an Instant can't be stored in an atomic
+    fn micros_since_start(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+}
+
+// Same approach as Statistics in problem 7: every field is its own atomic, so workers can update them without a lock
+// Durations are stored as whole microseconds, since there is no AtomicDuration
+struct WorkerStats {
+    jobs: AtomicU64,
+    busy_micros: AtomicU64,
+    // Idle time from finished idle stretches
+    idle_micros: AtomicU64,
+    // When the current idle stretch started (microseconds since the pool was created), or BUSY while running a job
+    // Without this, a worker that has been waiting for a long time would report almost no idle time until its next job arrives
+    idle_since_micros: AtomicU64,
+}
+
+// Marker for idle_since_micros while the worker is running a job
+const BUSY: u64 = u64::MAX;
+
+impl WorkerStats {
+    fn new(now_micros: u64) -> Self {
+        Self {
+            jobs: AtomicU64::new(0),
+            busy_micros: AtomicU64::new(0),
+            idle_micros: AtomicU64::new(0),
+            idle_since_micros: AtomicU64::new(now_micros),
+        }
+    }
+
+    // Called when a job starts: close the current idle stretch
+    fn start_job(&self, now_micros: u64) {
+        let idle_since = self.idle_since_micros.swap(BUSY, Ordering::SeqCst);
+        self.idle_micros.fetch_add(now_micros.saturating_sub(idle_since), Ordering::SeqCst);
+    }
+
+    // Called when a job ends: record it and start a new idle stretch
+    fn finish_job(&self, busy: Duration, now_micros: u64) {
+        self.jobs.fetch_add(1, Ordering::SeqCst);
+        self.busy_micros.fetch_add(busy.as_micros() as u64, Ordering::SeqCst);
+        self.idle_since_micros.store(now_micros, Ordering::SeqCst);
+    }
+
+    // Finished idle stretches plus the one in progress (if any)
+    fn idle(&self, now_micros: u64) -> Duration {
+        let idle_since = self.idle_since_micros.load(Ordering::SeqCst);
+        let current = if idle_since == BUSY { 0 } else { now_micros.saturating_sub(idle_since) };
+        Duration::from_micros(self.idle_micros.load(Ordering::SeqCst) + current)
+    }
+}
+
+// Upper bounds (in microseconds) of the histogram buckets: 100us, 1ms, 10ms, 100ms, 1s, 10s
+// Anything slower lands in the final "+Inf" bucket
+const BUCKET_BOUNDS_MICROS: [u64; 6] = [100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+// A histogram counts how many values fell into each range (bucket)
+// It tells you about the spread of latencies, which an average alone hides
+struct Histogram {
+    // One counter per bound, plus one for +Inf
+    buckets: [AtomicU64; BUCKET_BOUNDS_MICROS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            // AtomicU64 isn't Copy, so [AtomicU64::new(0); 7] doesn't work - std::array::from_fn builds each element separately
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        // .position() finds the first bucket whose bound is >= micros, and None means it goes in +Inf
+        let bucket = BUCKET_BOUNDS_MICROS
+            .iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MICROS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::SeqCst);
+        self.sum_micros.fetch_add(micros, Ordering::SeqCst);
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self.buckets.iter().map(|bucket| bucket.load(Ordering::SeqCst)).collect(),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::SeqCst)),
+            count: self.count.load(Ordering::SeqCst),
+        }
+    }
+}
+
+// Plain (non-atomic) copy of a Histogram at one point in time
+struct HistogramSnapshot {
+    // Count per bucket (not cumulative), the last one is +Inf
+    buckets: Vec<u64>,
+    sum: Duration,
+    count: u64,
+}
+
+struct WorkerMetrics {
+    id: usize,
+    jobs: u64,
+    busy: Duration,
+    idle: Duration,
+}
+
+// Everything metrics() reports
+// It is a snapshot: plain values copied out of the atomics, so it doesn't change while you look at it
+// Since each atomic is read separately, the numbers can be very slightly out of step with each other (same caveat as problem 7)
+struct PoolMetrics {
+    queue_depth: usize,
+    live_workers: usize,
+    // Workers running a job right now
+    active_workers: usize,
+    completed: u32,
+    panicked: u32,
+    cancelled: u32,
+    skipped: u32,
+    // Sorted by worker id
+    workers: Vec<WorkerMetrics>,
+    queue_wait: HistogramSnapshot,
+    run_time: HistogramSnapshot,
+}
+
+impl PoolMetrics {
+    // Renders the snapshot in the Prometheus text exposition format
+    // Each metric gets a # HELP line (description) and a # TYPE line (gauge, counter or histogram), followed by its samples
+    // Labels go in curly braces, e.g. threadpool_worker_jobs_total{worker="0"} 12
+    fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        // writeln!() into a String can't actually fail, but it still returns a fmt::Result, so we discard it with let _
+        let mut gauge = |name: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        gauge("threadpool_queue_depth", "Jobs waiting in the queue", self.queue_depth as f64);
+        gauge("threadpool_workers", "Worker threads in the pool", self.live_workers as f64);
+        gauge("threadpool_active_workers", "Worker threads currently running a job", self.active_workers as f64);
+
+        // {{ and }} are how you write a literal { or } inside of a format string
+        let _ = writeln!(out, "# HELP threadpool_jobs_total Jobs run by the pool, by outcome");
+        let _ = writeln!(out, "# TYPE threadpool_jobs_total counter");
+        let _ = writeln!(out, "threadpool_jobs_total{{outcome=\"completed\"}} {}", self.completed);
+        let _ = writeln!(out, "threadpool_jobs_total{{outcome=\"panicked\"}} {}", self.panicked);
+        let _ = writeln!(out, "threadpool_jobs_total{{outcome=\"cancelled\"}} {}", self.cancelled);
+        let _ = writeln!(out, "threadpool_jobs_total{{outcome=\"skipped\"}} {}", self.skipped);
+
+        Self::write_per_worker(&mut out, "threadpool_worker_jobs_total", "Jobs run by each worker", &self.workers, |w| w.jobs as f64);
+        Self::write_per_worker(&mut out, "threadpool_worker_busy_seconds_total", "Time each worker spent running jobs", &self.workers, |w| w.busy.as_secs_f64());
+        Self::write_per_worker(&mut out, "threadpool_worker_idle_seconds_total", "Time each worker spent waiting for jobs", &self.workers, |w| w.idle.as_secs_f64());
+
+        Self::write_histogram(&mut out, "threadpool_job_queue_wait_seconds", "Time jobs spent waiting in the queue", &self.queue_wait);
+        Self::write_histogram(&mut out, "threadpool_job_run_seconds", "Time jobs spent running", &self.run_time);
+
+        out
+    }
+
+    // One counter with a worker="id" label per worker
+    fn write_per_worker(out: &mut String, name: &str, help: &str, workers: &[WorkerMetrics], value: fn(&WorkerMetrics) -> f64) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for worker in workers {
+            let _ = writeln!(out, "{}{{worker=\"{}\"}} {}", name, worker.id, value(worker));
+        }
+    }
+
+    // Prometheus histogram buckets are cumulative: the le="0.01" bucket counts everything <= 10ms, including what is in le="0.001"
+    // So we keep a running total as we go
+    fn write_histogram(out: &mut String, name: &str, help: &str, histogram: &HistogramSnapshot) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        let mut cumulative = 0;
+        for (bound, count) in BUCKET_BOUNDS_MICROS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            let seconds = *bound as f64 / 1_000_000.0;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, seconds, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+        let _ = writeln!(out, "{}_sum {}", name, histogram.sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, histogram.count);
+    }
+}
+
+// Originally the jobs went through an mpsc channel
+// A channel only lets you send and receive - you can't ask it how many jobs are waiting, and a worker blocked in recv() can't tell how long it has been idle
+// To grow when the queue backs up and shrink when workers sit idle, we need to see both of those things
+// So the queue is now a collection that we guard ourselves (the same idea as TaskQueue in problem 10)
+// It is a BinaryHeap (a priority queue) so the most urgent job is always the one popped next
+struct State {
+    jobs: BinaryHeap<QueuedJob>,
+    // Incremented for every job, used to keep FIFO order among jobs with the same run_at
+    next_seq: u64,
+    // Set by join() - no more jobs are coming, so workers exit once the queue is drained
+    closed: bool,
+    // Workers that are running (busy or idle)
+    live_workers: usize,
+    // Workers that are currently waiting on the Condvar for a job
+    idle_workers: usize,
+    min_threads: usize,
+    max_threads: usize,
+    // Every worker gets a new id, even the ones spawned after a retirement
+    next_id: usize,
 }
 
 struct Worker {
@@ -61,101 +443,496 @@ struct Worker {
     thread: thread::JoinHandle<()>,
 }
 
-impl ThreadPool {
-    // This associated function is for making the threadpool
-    // It will spawn size number of threads
-    fn new(size: usize) -> Self {
-        // We are creating a transmitter (rx) and a receiver (rx)
-        // That will be sending and receiving Jobs
-        // mpsc = Multiple Producer, Single Consumer
-        // Multiple threads can send (tx can be cloned) but only one can receive (rx cannot be cloned)
-        // This is why we wrap rx in Arc<Mutex<>> below
-        let (tx, rx) = mpsc::channel::<Job>(); // We are creating a channel that will send jobs between threads (main -> worker)
-
-        // We are wrapping the receiver in Arc and Mutex
-        // Arc = Multiple ownership (each worker gets a clone pointing to same receiver)
-        // Mutex = Mutual exclusion (only one worker can recv() at a time)
-        // This pattern allows multiple workers to share a single receiver
-        // Pattern: Arc<Mutex<Receiver>> is standard for multi-consumer work queues
-        let rx = Arc::new(Mutex::new(rx));
-
-        // Creating a new Arc-wrapped AtomicU32 (starts at 0)
-        // We need Arc here since it is not globally accessible (like 'static)
-        // Arc allows multiple workers to share the same counter
-        let completed_count = Arc::new(AtomicU32::new(0));
-
-        // This will house our vector of Worker structs
-        let mut workers = Vec::new();
-
-        // Loop size times to spawn size workers
-        for worker_id in 0..size {
-
-                // Making new pointers to the same data
-                let rx_clone = Arc::clone(&rx);
-                let completed_clone = Arc::clone(&completed_count);
-
-                // We are spawning a thread
-                // Handles allow us to interact with spawned threads
-                let handle = thread::spawn(move || {
-                    // We are moving the captured variables into the closure
-                    // This is so the thread can continue to use them after the loop iteration ends
-
-                    // We are just using loop here since we do not know the number of messages each thread will receive
-                    // Worker pattern: loop forever until channel closes
-                    // 1. Try to receive a job 
-                    // 2. Execute the job
-                    // 3. Update the completed count
-                    // 4. Loop back (repeat until channel closes)
-                    loop {
-                        
-                        // The thread can acquire the lock, allowing it to receive a message
-                        // We use .unwrap() in case the thread panics
-                        // .recv() returns a Result, this is why we need to match
-                        // After receiving, the thread immediately releases the lock so another thread can pick it up
-                        let recv = rx_clone.lock().unwrap().recv(); // Lock is acquired and dropped here
-                        // Minimal lock scope -> lock released at semicolon
-
-                        // If we put rx_clone.lock().unwrap().recv() directly instead of the intermediate recv variable in the match expression
-                        // the first spawned thread would hold the lock for all of the work and the other threads wouldn't be able to pick up the lock
-                        // This is because temporaries in match expressions live until end of the match
-                        // So the MutexGuard would not be dropped until an Err was encountered and the loop breaks
-                        // Which, if that happens, there is nothing else being sent, since all the work is already finished
-                        match recv {
-                            // If the receiver gets a message (Ok(job)) it unwraps and it assigns it to job
-                            Ok(job) => {
-                                // We print some information
-                                println!("Worker {} executing job", worker_id);
-                                // We execute the job (since it is a closure)
-                                job();
-                                // We increment the counter but discard the old value
-                                completed_clone.fetch_add(1, Ordering::SeqCst);
-                            }
-                            // Channel closed (sender was dropped)
-                            // No more jobs coming, so exit the loop and let thread finish
-                            Err(_) => break,
-                        }
-                    }
-                });
-                
-                // Pushing size Worker structs to the vector
-                workers.push(Worker {
-                    id: worker_id,
-                    thread:handle,
-                })
-        } 
-
-        // Returning the ThreadPool
-        // It can now be used with ThreadPool::new(4)
+impl Worker {
+    // Spawns one worker thread that pulls jobs off the shared queue until it retires or the pool is closed
+    // The caller must already have counted this worker in state.live_workers
+    fn new(id: usize, shared: Arc<Shared>) -> Self {
+        // We are spawning a thread
+        // Handles allow us to interact with spawned threads
+        let thread = thread::spawn(move || {
+            // We are moving the captured variables into the closure
+            // This is so the thread can continue to use them after the loop iteration ends
+
+            // The sentinel lives for as long as this thread's stack does
+            // If anything below panics outside of catch_unwind, the sentinel gets dropped while unwinding and respawns this worker
+            let sentinel = Sentinel { id, shared: &shared };
+
+            // Register this worker's counters so metrics() can find them
+            // A replacement for a dead worker reuses the id, so it simply takes over the old entry
+            let stats = Arc::new(WorkerStats::new(shared.micros_since_start()));
+            shared.worker_stats.lock().unwrap().insert(id, Arc::clone(&stats));
+
+            // We are just using loop here since we do not know the number of jobs each thread will receive
+            // Worker pattern: loop until there is nothing left for this worker to do
+            // 1. Wait for a job (or decide to retire)
+            // 2. Execute the job (catching any panic)
+            // 3. Update the completed or panicked count
+            // 4. Loop back
+            while let Some(queued) = Worker::next_job(&shared) {
+                let started = Instant::now();
+                stats.start_job(shared.micros_since_start());
+                shared.queue_wait.record(started - queued.queued_at);
+                let job = queued.job;
+
+                // We print some information
+                println!("Worker {} executing job", id);
+
+                // We execute the job (since it is a closure) inside of catch_unwind
+                // catch_unwind() runs the closure and turns a panic into an Err instead of unwinding through our loop
+                // Without this, one bad job would kill the worker thread and the pool would silently lose capacity
+                // AssertUnwindSafe is us promising the compiler that nothing the job touches is left in a broken state if it panics
+                // The job is a Box<dyn FnOnce()>, which isn't UnwindSafe by default, so we need to make that promise explicitly
+                match panic::catch_unwind(AssertUnwindSafe(job)) {
+                    // We increment the counter but discard the old value
+                    Ok(()) => shared.completed_count.fetch_add(1, Ordering::SeqCst),
+                    // The panic message has already been printed by the panic hook
+                    // The payload itself is handed to the caller through the TaskHandle (see spawn()), so we only count it here
+                    Err(_) => shared.panicked_count.fetch_add(1, Ordering::SeqCst),
+                };
+
+                let run_time = started.elapsed();
+                stats.finish_job(run_time, shared.micros_since_start());
+                shared.run_time.record(run_time);
+            }
+
+            // This worker is gone, so it no longer shows up in metrics()
+            // Its jobs are still included in the pool-wide counters and histograms
+            shared.worker_stats.lock().unwrap().remove(&id);
+
+            // Normal exit - we don't want a replacement
+            // mem::forget() skips the sentinel's Drop (nothing is leaked since it only holds a reference)
+            std::mem::forget(sentinel);
+        });
+
+        Self { id, thread }
+    }
+
+    // Blocks until there is a job for this worker
+    // Returns None when the worker should exit, in which case it has already removed itself from live_workers
+    fn next_job(shared: &Shared) -> Option<QueuedJob> {
+        // The lock is held while we look at the queue, but .wait_timeout() releases it while we sleep
+        // And the MutexGuard is dropped when we return, so the job itself runs without holding the lock
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            // resize() lowered max_threads below the number of running workers, so the extra workers retire
+            // They only retire between jobs, and the queued jobs stay in the queue for the remaining workers
+            if state.live_workers > state.max_threads {
+                return Worker::exit(shared, &mut state);
+            }
+
+            // .pop() = the job with the earliest run_at (see QueuedJob)
+            if let Some(queued) = state.jobs.pop() {
+                // Nobody wants this job anymore - drop it and look at the next one
+                // This is the only place a job's fate is decided, so a job is either run or counted here, never both
+                if queued.token.as_ref().is_some_and(|token| token.is_cancelled()) {
+                    shared.cancelled_count.fetch_add(1, Ordering::SeqCst);
+                    continue;
+                }
+                if queued.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    shared.skipped_count.fetch_add(1, Ordering::SeqCst);
+                    continue;
+                }
+                return Some(queued);
+            }
+
+            // The queue is drained and join() was called - nothing else is ever coming
+            if state.closed {
+                return Worker::exit(shared, &mut state);
+            }
+
+            // Nothing to do, so sleep until execute() notifies us or keep_alive passes
+            // We loop afterwards because Condvars can wake up spuriously
+            state.idle_workers += 1;
+            let (guard, timeout) = shared.available.wait_timeout(state, shared.keep_alive).unwrap();
+            state = guard;
+            state.idle_workers -= 1;
+
+            // Idle for a whole keep_alive with no work - retire if the pool is above its minimum size
+            if timeout.timed_out() && state.jobs.is_empty() && !state.closed && state.live_workers > state.min_threads {
+                return Worker::exit(shared, &mut state);
+            }
+        }
+    }
+
+    // Takes every job out of the queue, in the order they would have run
+    fn drain(state: &mut State) -> Vec<Job> {
+        let mut jobs = Vec::with_capacity(state.jobs.len());
+        while let Some(queued) = state.jobs.pop() {
+            jobs.push(queued.job);
+        }
+        jobs
+    }
+
+    // Removes this worker from the count and lets shutdown_graceful() know
+    // Always returns None so next_job() can use it as its return value
+    fn exit(shared: &Shared, state: &mut State) -> Option<QueuedJob> {
+        state.live_workers -= 1;
+        shared.exited.notify_all();
+        None
+    }
+}
+
+// A sentinel is a guard value whose only job is to run code in its Drop
+// Drop runs both when a value goes out of scope normally AND when the thread unwinds because of a panic
+// thread::panicking() tells us which of the two is happening
+struct Sentinel<'a> {
+    id: usize,
+    shared: &'a Arc<Shared>,
+}
+
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            println!("Worker {} died, spawning a replacement", self.id);
+            // The replacement takes over the dead worker's spot in live_workers, so the count doesn't change
+            // It gets the same id and is pushed to the shared list so join() still waits on it
+            let replacement = Worker::new(self.id, Arc::clone(self.shared));
+            self.shared.workers.lock().unwrap().push(replacement);
+        }
+    }
+}
+
+// Bookkeeping shared between a scope and the jobs spawned inside it
+// It lives in an Arc (not on scope()'s stack) so a job can still safely touch it after it has told scope() it is done
+struct ScopeState {
+    // Jobs spawned in the scope that haven't finished yet
+    pending: Mutex<usize>,
+    // Notified when pending reaches 0
+    all_done: Condvar,
+    // The first panic from a scoped job, re-raised by scope() once everything has finished
+    panic: Mutex<Option<PanicPayload>>,
+}
+
+// The handle passed to the closure given to ThreadPool::scope()
+// It mirrors std::thread::Scope from problem 14, including its two lifetimes:
+// 'env = how long the borrowed data (and the pool) live - everything outside of the scope() call
+// 'scope = the scope itself - spawned jobs can borrow anything that lives at least this long
+struct Scope<'scope, 'env: 'scope> {
+    pool: &'env ThreadPool,
+    state: Arc<ScopeState>,
+    // PhantomData lets a struct act as if it holds a type without actually storing it
+    // &'a mut &'a () makes the lifetime "invariant" - the compiler can't quietly shrink or stretch it
+    // Without this, a Scope<'long> could be treated as a Scope<'short>, and jobs could borrow data that dies before the scope ends
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    // Queues f on the pool
+    // Unlike execute(), f only needs to live for 'scope, not 'static, so it can borrow local variables
+    fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        // Count the job before it is queued, so scope() can't see pending == 0 while it is still waiting to run
+        *self.state.pending.lock().unwrap() += 1;
+
+        let state = Arc::clone(&self.state);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let panicked = result.is_err();
+
+            // Keep the first panic for scope() to re-raise
+            if let Err(payload) = result {
+                state.panic.lock().unwrap().get_or_insert(payload);
+            }
+
+            // f (and everything it borrowed) is gone by now, so it is safe to let scope() return
+            let mut pending = state.pending.lock().unwrap();
+            *pending -= 1;
+            if *pending == 0 {
+                state.all_done.notify_all();
+            }
+            drop(pending);
+
+            // Same as spawn(): let the worker count the panic
+            if panicked {
+                panic::resume_unwind(Box::new("scoped job panicked"));
+            }
+        });
+
+        // The queue only holds Job = Box<dyn FnOnce() + Send + 'static>, so we have to pretend the job is 'static
+        // transmute() reinterprets a value as another type - here it only changes the lifetime, the layout is identical
+        // SAFETY: scope() does not return until pending is back to 0, so the job always finishes before 'scope ends
+        // And the pool can't be shut down (which could drop the job without running it) while scope() is borrowing it
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.push_job(Priority::Medium, job, None, None);
+    }
+}
+
+// What shutdown_graceful() hands back when the timeout passes before everything finished
+struct ShutdownTimedOut {
+    // Jobs that were still running at the deadline
+    // Their workers can't be interrupted - they exit on their own as soon as the job returns
+    running: usize,
+    // Jobs that never started, in the order they would have run
+    not_started: Vec<Job>,
+    // The worker threads that hadn't exited yet
+    // Handed over instead of thrown away, so the caller decides whether (and when) to wait for them - nothing is left detached
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl ShutdownTimedOut {
+    // Waits for the workers that were still running at the deadline
+    fn join_workers(self) {
+        for worker in self.workers {
+            // Same as ThreadPool::join_workers(): a worker that died was already replaced, so its Err is ignored
+            let _ = worker.join();
+        }
+    }
+}
+
+// Builder for a pool whose size can change at runtime (same pattern as ThreadPoolBuilder in problem 16)
+// min_threads = workers that are always kept around, even when idle
+// max_threads = upper limit the pool grows to when jobs back up
+// keep_alive = how long a worker above min_threads may sit idle before it retires
+// aging = how long a queued job has to wait to be treated as one priority level higher
+struct ThreadPoolBuilder {
+    min_threads: Option<usize>,
+    max_threads: Option<usize>,
+    keep_alive: Option<Duration>,
+    aging: Option<Duration>,
+}
+
+impl ThreadPoolBuilder {
+    fn new() -> Self {
         Self {
-            workers, // vector of Worker structs
-            sender: tx,
-            completed_count, 
+            min_threads: None,
+            max_threads: None,
+            keep_alive: None,
+            aging: None,
         }
+    }
+
+    fn set_min_threads(mut self, n: usize) -> Self {
+        self.min_threads = Some(n);
+        self
+    }
+
+    fn set_max_threads(mut self, n: usize) -> Self {
+        self.max_threads = Some(n);
+        self
+    }
 
+    fn keep_alive(mut self, duration: Duration) -> Self {
+        self.keep_alive = Some(duration);
+        self
+    }
+
+    fn aging(mut self, duration: Duration) -> Self {
+        self.aging = Some(duration);
+        self
+    }
+
+    fn build(self) -> ThreadPool {
         // It is common to match the number of CPU cores when making a new threadpool
-            // let num_cpus = std::thread::available_parallelism()
-            //     .map(|n| n.get())
-            //     .unwrap_or(4);
+        let num_cpus = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+
+        let min_threads = self.min_threads.unwrap_or(1);
+        // max_threads can never be below min_threads, and we always need at least one worker or queued jobs would never run
+        let max_threads = self.max_threads.unwrap_or(num_cpus).max(min_threads).max(1);
+        let keep_alive = self.keep_alive.unwrap_or(Duration::from_secs(1));
+        let aging = self.aging.unwrap_or(Duration::from_millis(500));
+
+        // Creating the shared state with both counters starting at 0
+        // We need Arc here since it is not globally accessible (like 'static)
+        // Arc allows multiple workers to share the same counters and queue
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                jobs: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+                live_workers: min_threads,
+                idle_workers: 0,
+                min_threads,
+                max_threads,
+                next_id: min_threads,
+            }),
+            available: Condvar::new(),
+            exited: Condvar::new(),
+            keep_alive,
+            aging,
+            started: Instant::now(),
+            completed_count: AtomicU32::new(0),
+            panicked_count: AtomicU32::new(0),
+            cancelled_count: AtomicU32::new(0),
+            skipped_count: AtomicU32::new(0),
+            workers: Mutex::new(Vec::new()),
+            worker_stats: Mutex::new(HashMap::new()),
+            queue_wait: Histogram::new(),
+            run_time: Histogram::new(),
+        });
+
+        let pool = ThreadPool { shared };
+
+        // The pool starts with min_threads workers and grows from there
+        for worker_id in 0..min_threads {
+            pool.add_worker(worker_id);
+        }
+
+        pool
+    }
+}
+
+// A one-shot slot is a place where exactly one value gets written exactly once
+// The worker that runs the job writes the result into the slot, and the caller holding the TaskHandle reads it out
+// Mutex<SlotState<T>> = the result itself (Pending until the worker fills it in)
+// Condvar = lets the caller sleep until the worker signals "the value is ready" instead of spinning in a loop
+// A Condvar is always paired with a Mutex - you wait on the Condvar while holding the lock, and the wait releases the lock while sleeping
+struct Slot<T> {
+    state: Mutex<SlotState<T>>,
+    ready: Condvar,
+}
+
+enum SlotState<T> {
+    // The job hasn't finished (or hasn't started) yet
+    Pending,
+    Ready(T),
+    // The job was dropped without running (shutdown_now(), or a timed out shutdown_graceful()), so nothing will ever be written
+    // Without this state, join() would wait on the Condvar forever
+    Closed,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(SlotState::Pending),
+            ready: Condvar::new(),
+        }
+    }
+
+    // Called by the worker once the job has finished
+    // We store the value and wake up anybody waiting in join()/join_timeout()
+    fn fill(&self, value: T) {
+        *self.state.lock().unwrap() = SlotState::Ready(value);
+        // notify_all() wakes every thread blocked on this Condvar
+        // There is only ever one waiter (the handle owner), but notify_all() is the safe default
+        self.ready.notify_all();
+    }
+
+    // Called when the job is dropped - does nothing if the job already filled the slot
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let SlotState::Pending = *state {
+            *state = SlotState::Closed;
+            self.ready.notify_all();
+        }
+    }
+}
+
+// Lives inside the closure spawn() queues, so it is dropped together with it:
+// - The job ran: the slot is already filled, so close() does nothing
+// - The job was dropped without running: close() tells the TaskHandle nothing is coming
+struct CloseOnDrop<T>(Arc<Slot<T>>);
+
+impl<T> Drop for CloseOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+// Why a TaskHandle didn't return a value
+#[derive(Debug)]
+enum TaskError {
+    // The job panicked - same payload std::thread::JoinHandle::join() would give
+    Panicked(PanicPayload),
+    // The job was thrown away before it ever ran
+    Dropped,
+}
+
+// TaskHandle<T> is our version of std::thread::JoinHandle<T>, but for a job that runs on the pool
+// It does not own a thread - it only owns a pointer to the slot the worker will eventually fill in
+// T is the return type of the closure passed to spawn()
+// The slot holds a Result so a panicking job can hand its payload back to the caller, just like JoinHandle::join()
+struct TaskHandle<T> {
+    slot: Arc<Slot<Result<T, PanicPayload>>>,
+}
+
+impl<T> TaskHandle<T> {
+    // Blocking: wait for as long as it takes for the job to finish (or be dropped)
+    // Consumes self since the result can only be taken out once
+    // Ok(value) = the job returned normally, Err(Panicked) = the job panicked, Err(Dropped) = the job never ran
+    fn join(self) -> Result<T, TaskError> {
+        let mut state = self.slot.state.lock().unwrap();
+        // We loop because Condvars can have "spurious wakeups" - the thread can wake up without anyone calling notify
+        // So we always re-check the condition after waking
+        while let SlotState::Pending = *state {
+            // .wait() releases the lock, sleeps, and re-acquires the lock before returning
+            state = self.slot.ready.wait(state).unwrap();
+        }
+        Self::take(&mut state).unwrap()
+    }
+
+    // Non-blocking: check once and return immediately
+    // Ok(result) = the job is done (result is the same as what join() would return)
+    // Err(self) = not done yet, so we hand the handle back so the caller can try again later
+    fn try_join(self) -> Result<Result<T, TaskError>, Self> {
+        // We bind the result to a variable so the MutexGuard is dropped before we move self into Err
+        let result = Self::take(&mut self.slot.state.lock().unwrap());
+        match result {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+
+    // Blocking, but only for up to timeout
+    // Same return shape as try_join() - Err(self) means the deadline passed before the job finished
+    fn join_timeout(self, timeout: Duration) -> Result<Result<T, TaskError>, Self> {
+        // We compute an absolute deadline so spurious wakeups don't reset the clock
+        let deadline = Instant::now() + timeout;
+        let result = {
+            let mut state = self.slot.state.lock().unwrap();
+            while let SlotState::Pending = *state {
+                // .checked_duration_since() returns None once the deadline is in the past
+                let remaining = match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => remaining,
+                    _ => break,
+                };
+                // .wait_timeout() is like .wait() but gives up after remaining
+                state = self.slot.ready.wait_timeout(state, remaining).unwrap().0;
+            }
+            Self::take(&mut state)
+        };
+        match result {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+
+    // None while the job is still pending
+    // Taking a Ready value leaves Closed behind - the handle is consumed right after, so nobody looks again
+    fn take(state: &mut SlotState<Result<T, PanicPayload>>) -> Option<Result<T, TaskError>> {
+        match std::mem::replace(state, SlotState::Closed) {
+            SlotState::Pending => {
+                *state = SlotState::Pending;
+                None
+            }
+            SlotState::Ready(result) => Some(result.map_err(TaskError::Panicked)),
+            SlotState::Closed => Some(Err(TaskError::Dropped)),
+        }
+    }
+}
+
+impl ThreadPool {
+    // This associated function is for making a fixed-size threadpool
+    // It will spawn size number of threads, and the pool never grows or shrinks on its own
+    fn new(size: usize) -> Self {
+        ThreadPoolBuilder::new()
+            .set_min_threads(size)
+            .set_max_threads(size)
+            .build()
+    }
+
+    // Spawns a worker and keeps its handle so join() can wait on it
+    // The caller must already have counted it in state.live_workers
+    fn add_worker(&self, worker_id: usize) {
+        let worker = Worker::new(worker_id, Arc::clone(&self.shared));
+        let mut workers = self.shared.workers.lock().unwrap();
+        // Workers that retired have already finished, so there is no reason to keep their handles around
+        workers.retain(|worker| !worker.thread.is_finished());
+        workers.push(worker);
     }
 
     // This function takes a generic as input
@@ -163,53 +940,405 @@ impl ThreadPool {
     // 1. Callable once (implement the FnOnce() trait)
     // 2. Be able to be sent across threads (Send)
     // 3. Does not reference any short-lived data ('static)
+    // Jobs queued with execute() get Priority::Medium
     fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
-    {   
+    {
+        self.execute_with_priority(Priority::Medium, f);
+    }
+
+    // Same as execute(), but the job jumps ahead of anything with a lower priority that was queued around the same time
+    fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
         // Since f has an unknown size at compile time, it has to be put in a Box
         // Box the closure to make it a Job (Box<dyn FnOnce() + Send + 'static>)
-        let job = Box::new(f);
+        self.push_job(priority, Box::new(f), None, None);
+    }
 
-        // Now we are using the sender (tx) to send jobs to the receiver (rx)
-        // Send the job through the channel to the workers
-        // One of the workers will receive and execute it
+    // Same as execute(), but the job is skipped if token is cancelled before a worker picks it up
+    // The job is handed its own clone of the token, so it can also check it while it runs
+    fn execute_with_token<F>(&self, token: &CancellationToken, f: F)
+    where
+        F: FnOnce(&CancellationToken) + Send + 'static,
+    {
+        let job_token = token.clone();
+        self.push_job(
+            Priority::Medium,
+            Box::new(move || f(&job_token)),
+            Some(token.clone()),
+            None,
+        );
+    }
+
+    // Same as execute(), but the job is skipped if it is still in the queue when deadline passes
+    // Useful for work that is pointless when late, like answering a request whose caller has already timed out
+    // A job that starts before the deadline is allowed to finish, even if that takes it past the deadline
+    fn execute_with_deadline<F>(&self, deadline: Instant, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push_job(Priority::Medium, Box::new(f), None, Some(deadline));
+    }
+
+    // Queues an already boxed job
+    // Every execute_*() method and Scope::spawn() end up here
+    fn push_job(&self, priority: Priority, job: Job, token: Option<CancellationToken>, deadline: Option<Instant>) {
+        // We push the job onto the shared queue
+        // One of the workers will pick it up and execute it
         // This does not wait for the job to complete - it queues it - it does not wait for the job to actually finish running
-        // The main thread continues immediately after sending -> returns immediately
+        // The main thread continues immediately after queuing -> returns immediately
         // This is important so you can submit many jobs quickly and they run concurrently, the main thread doesn't freeze, and allows for throughput
-        self.sender.send(job).unwrap();
+        let new_worker = {
+            let mut state = self.shared.state.lock().unwrap();
+
+            // See QueuedJob for why this gives us aging
+            let run_at = self.shared.started.elapsed() + self.shared.aging * priority.levels_below_critical();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.jobs.push(QueuedJob { run_at, seq, queued_at: Instant::now(), token, deadline, job });
+
+            // The queue is backing up: there are more jobs waiting than idle workers to take them
+            // If we are still below max_threads, grow the pool by one worker
+            if state.jobs.len() > state.idle_workers && state.live_workers < state.max_threads {
+                state.live_workers += 1;
+                state.next_id += 1;
+                Some(state.next_id - 1)
+            } else {
+                None
+            }
+        }; // Lock released here, before we wake anybody up or spawn a thread
+
+        // Wake up one sleeping worker (if there is one) to take the job
+        self.shared.available.notify_one();
+
+        if let Some(worker_id) = new_worker {
+            self.add_worker(worker_id);
+        }
         // Fire and forget
         // Difference between queuing work and doing work
     }
 
+    // Changes the pool to exactly n workers while it is running
+    // Growing spawns the missing workers right away
+    // Shrinking lets the extra workers finish their current job and retire - queued jobs stay in the queue, so nothing is lost
+    // After resize(n), min_threads and max_threads are both n
+    fn resize(&self, n: usize) {
+        // A pool with zero workers would leave queued jobs sitting there forever
+        let n = n.max(1);
+
+        let new_workers: Vec<usize> = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.min_threads = n;
+            state.max_threads = n;
+
+            let missing = n.saturating_sub(state.live_workers);
+            state.live_workers += missing;
+            let first_id = state.next_id;
+            state.next_id += missing;
+            (first_id..first_id + missing).collect()
+        };
+
+        // Wake everybody up so idle workers notice they are over max_threads and retire
+        self.shared.available.notify_all();
+
+        for worker_id in new_workers {
+            self.add_worker(worker_id);
+        }
+    }
+
+    // Takes a snapshot of the pool's counters
+    fn metrics(&self) -> PoolMetrics {
+        // Read the queue and worker counts together so they line up with each other
+        let (queue_depth, live_workers, idle_workers) = {
+            let state = self.shared.state.lock().unwrap();
+            (state.jobs.len(), state.live_workers, state.idle_workers)
+        };
+
+        let mut workers: Vec<WorkerMetrics> = self
+            .shared
+            .worker_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, stats)| WorkerMetrics {
+                id,
+                jobs: stats.jobs.load(Ordering::SeqCst),
+                busy: Duration::from_micros(stats.busy_micros.load(Ordering::SeqCst)),
+                idle: stats.idle(self.shared.micros_since_start()),
+            })
+            .collect();
+        // HashMap iteration order is random, so sort to make the output stable
+        workers.sort_by_key(|worker| worker.id);
+
+        PoolMetrics {
+            queue_depth,
+            live_workers,
+            active_workers: live_workers - idle_workers,
+            completed: self.completed(),
+            panicked: self.panicked_count(),
+            cancelled: self.cancelled_count(),
+            skipped: self.skipped_count(),
+            workers,
+            queue_wait: self.shared.queue_wait.snapshot(),
+            run_time: self.shared.run_time.snapshot(),
+        }
+    }
+
+    // How many workers the pool currently has (busy or idle)
+    fn num_threads(&self) -> usize {
+        self.shared.state.lock().unwrap().live_workers
+    }
+
+    // spawn() is like execute() but the closure can return a value
+    // Instead of throwing the return value away, we hand back a TaskHandle<T> that the caller can join on
+    // T: Send since the value is produced on the worker thread and read on the caller's thread
+    // T: 'static since the slot can outlive the caller's stack frame
+    fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // One slot per job
+        // One pointer goes into the job (for the worker to fill in), one goes into the handle (for the caller to read)
+        let slot = Arc::new(Slot::new());
+        // The job's pointer is wrapped in CloseOnDrop, so the handle finds out if the job is dropped without running
+        let worker_slot = CloseOnDrop(Arc::clone(&slot));
+
+        // We wrap f in another closure with no return value so it still fits the Job type alias
+        // The workers don't need to know anything about T - they just call the job like before
+        self.execute(move || {
+            // We catch the panic here (instead of only in the worker) so we can hand the payload to the caller
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let panicked = result.is_err();
+            worker_slot.0.fill(result);
+
+            // The payload now belongs to the caller, but the worker still needs to know the job panicked so it can count it
+            // resume_unwind() re-raises a panic without calling the panic hook again (so the message isn't printed twice)
+            if panicked {
+                panic::resume_unwind(Box::new("job panicked"));
+            }
+        });
+
+        TaskHandle { slot }
+    }
+
+    // Runs f with a Scope that can spawn jobs borrowing local data, then waits for every one of those jobs
+    // Same shape as std::thread::scope(): for<'scope> means f has to work for whatever 'scope the call ends up with
+    // If f or any spawned job panics, the panic is re-raised here, but only after every job has finished
+    // Note: calling scope() from inside one of this pool's own jobs can deadlock if every worker ends up waiting in scope()
+    fn scope<'env, F, T>(&'env self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                all_done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        // We catch a panic from f so we still wait for the jobs it already spawned
+        // Returning early would let them keep using data that is about to be dropped
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        let mut pending = scope.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = scope.state.all_done.wait(pending).unwrap();
+        }
+        drop(pending);
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(value) => {
+                if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+                    panic::resume_unwind(payload);
+                }
+                value
+            }
+        }
+    }
+
     fn completed(&self) -> u32 {
-        // Since self.completed_count is Arc<AtomicU32>, not u32, we need to load it
+        // Since self.shared.completed_count is AtomicU32, not u32, we need to load it
         // When we load the atomic value, it will give us a u32 in return
-        self.completed_count.load(Ordering::SeqCst)
+        self.shared.completed_count.load(Ordering::SeqCst)
+    }
+
+    // Number of jobs that panicked instead of completing
+    // completed() + panicked_count() = total number of jobs that have been run
+    fn panicked_count(&self) -> u32 {
+        self.shared.panicked_count.load(Ordering::SeqCst)
+    }
+
+    // Number of jobs that were never run because their token was cancelled while they were queued
+    // Jobs that saw the cancellation themselves and returned early count as completed
+    fn cancelled_count(&self) -> u32 {
+        self.shared.cancelled_count.load(Ordering::SeqCst)
+    }
+
+    // Number of jobs that were never run because their deadline passed while they were queued
+    fn skipped_count(&self) -> u32 {
+        self.shared.skipped_count.load(Ordering::SeqCst)
     }
 
     // self is consumed here so ThreadPool can't be used after .join() is called
-    // This is by design - once we have waited for all workers to finish and shut down, the pool is no longer functional (channel is closed, workers exited)
+    // This is by design - once we have waited for all workers to finish and shut down, the pool is no longer functional (queue is closed, workers exited)
     // Consuming self prevents accidentally trying to use a shutdown pool
     fn join(self) {
+        // Drop does all of the work (see impl Drop for ThreadPool below)
+        // join() just gives that a name, so the caller can make it explicit
+        drop(self);
+    }
 
-        // We need to drop the sender so that the receivers know to stop receiving
-        // They will always keep receiving if they know a sender is out there
-        // This prevents them from waiting forever
-        drop(self.sender);
+    // Like join(), but only waits for up to timeout
+    // Ok(()) = every queued job finished in time
+    // Err(ShutdownTimedOut) = the deadline passed - the report says what was still running, and hands back what never started
+    // along with the handles of the workers that are still busy
+    fn shutdown_graceful(self, timeout: Duration) -> Result<(), ShutdownTimedOut> {
+        let deadline = Instant::now() + timeout;
+        self.close();
 
-        // Using .join() so all the spawned threads join the main thread
-        // This allows all the spawned threads to finish before the main thread continues
-        for worker in self.workers {
-            worker.thread.join().unwrap();
+        let report = {
+            let mut state = self.shared.state.lock().unwrap();
+            // Same pattern as TaskHandle::join_timeout(): sleep on a Condvar until the condition holds or the deadline passes
+            while state.live_workers > 0 {
+                let remaining = match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => remaining,
+                    _ => break,
+                };
+                state = self.shared.exited.wait_timeout(state, remaining).unwrap().0;
+            }
+
+            if state.live_workers == 0 {
+                None
+            } else {
+                // Take the rest of the queue so the workers stop picking up new jobs
+                let not_started = Worker::drain(&mut state);
+                // Any worker that isn't idle is in the middle of a job
+                let running = state.live_workers - state.idle_workers;
+                Some(ShutdownTimedOut { running, not_started, workers: Vec::new() })
+            }
+        };
+
+        match report {
+            // Every worker has exited, so joining them is instant
+            None => {
+                self.join_workers();
+                Ok(())
+            }
+            Some(mut report) => {
+                // We hand the workers that are still busy to the caller
+                // Otherwise Drop would wait for them, which is exactly what the timeout is meant to avoid
+                // The queue is empty and closed, so each of them exits right after its current job
+                let workers = std::mem::take(&mut *self.shared.workers.lock().unwrap());
+                report.workers = workers.into_iter().map(|worker| worker.thread).collect();
+                Err(report)
+            }
+        }
+    }
+
+    // Stops right away: nothing else from the queue gets started
+    // The jobs that were waiting are handed back to the caller instead of being dropped, in the order they would have run
+    // If the caller drops one that came from spawn(), its TaskHandle::join() returns Err(TaskError::Dropped)
+    // Jobs that are already running can't be interrupted, so we still wait for those to finish
+    fn shutdown_now(self) -> Vec<Job> {
+        let pending = {
+            let mut state = self.shared.state.lock().unwrap();
+            Worker::drain(&mut state)
+        };
+        // Drop closes the (now empty) queue and joins the workers
+        drop(self);
+        pending
+    }
+
+    // Tells the workers that no more jobs are coming
+    // Otherwise idle workers would keep waiting on the Condvar forever
+    // They still drain whatever is left in the queue before exiting
+    fn close(&self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.available.notify_all();
+    }
+
+    // Using .join() so all the spawned threads join the main thread
+    // This allows all the spawned threads to finish before the main thread continues
+    fn join_workers(&self) {
+        // We pop one worker at a time (instead of a for loop) since a dying worker may push a replacement while we are joining
+        // The lock is only held for the .pop(), not while we wait on the thread
+        loop {
+            let worker = self.shared.workers.lock().unwrap().pop();
+            match worker {
+                // A worker that died has already been replaced, so its Err is not a problem - we just ignore it
+                Some(worker) => {
+                    let _ = worker.thread.join();
+                }
+                None => break,
+            }
         }
     }
 
     // Graceful shutdown pattern:
-    // Step 1: Drop the sender so workers know no more jobs are coming -> .recv() returns Err, causing workers to break from the loop
+    // Step 1: Mark the queue as closed so workers know no more jobs are coming -> next_job() returns None once the queue is empty, causing workers to break from the loop
     // Step 2: Wait for each worker thread to finish its current job and exit
 }
 
+// Drop runs when the pool goes out of scope (or is passed to drop())
+// Without this, the workers would be left running in the background with nobody to join them
+// Calling it twice is harmless: the second time the queue is already closed and the worker list is empty
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.close();
+        self.join_workers();
+    }
+}
+
+// The same helpers as problem 14, but running on a shared pool instead of spawning new threads every call
+fn parallel_sum(pool: &ThreadPool, data: &[i32], num_chunks: usize) -> i32 {
+    let chunk_size = data.len().div_ceil(num_chunks.max(1)).max(1);
+    // One partial sum per chunk - each job gets a &mut to its own slot, so no locking is needed
+    let mut partials = vec![0; data.len().div_ceil(chunk_size)];
+
+    pool.scope(|s| {
+        for (chunk, partial) in data.chunks(chunk_size).zip(partials.iter_mut()) {
+            s.spawn(move || *partial = chunk.iter().sum());
+        }
+    });
+
+    partials.iter().sum()
+}
+
+fn parallel_map<T, U, F>(pool: &ThreadPool, data: &[T], num_chunks: usize, func: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    let chunk_size = data.len().div_ceil(num_chunks.max(1)).max(1);
+    // U has no default value, so every output starts as None and gets filled in by the jobs
+    let mut output: Vec<Option<U>> = (0..data.len()).map(|_| None).collect();
+    let func = &func;
+
+    pool.scope(|s| {
+        // .chunks_mut() splits the output into non-overlapping &mut slices, one per job
+        for (input, output) in data.chunks(chunk_size).zip(output.chunks_mut(chunk_size)) {
+            s.spawn(move || {
+                for (value, slot) in input.iter().zip(output.iter_mut()) {
+                    *slot = Some(func(value));
+                }
+            });
+        }
+    });
+
+    // scope() only returns once every job is done, so every slot is Some
+    output.into_iter().map(|value| value.unwrap()).collect()
+}
+
 fn main() {
 
     // Spawn a pool with 4 worker threads
@@ -224,7 +1353,261 @@ fn main() {
         });
     }
 
+    // Submit 5 jobs that return values
+    // Each call gives us back a TaskHandle<u64> right away - the work happens in the background
+    let handles: Vec<TaskHandle<u64>> = (1..=5)
+        .map(|n| {
+            pool.spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                n * n
+            })
+        })
+        .collect();
+
+    // Blocking join: waits until each job is done
+    let squares: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    println!("Squares: {:?}", squares);
+
+    // Non-blocking try_join and join_timeout
+    let slow = pool.spawn(|| {
+        thread::sleep(Duration::from_millis(300));
+        "slow job done"
+    });
+
+    // The job has only just been queued, so this will almost certainly hand the handle back
+    match slow.try_join() {
+        Ok(value) => println!("try_join: {:?}", value.ok()),
+        Err(slow) => {
+            println!("try_join: not ready yet");
+
+            // Wait for up to 5 seconds - more than enough for the job to finish
+            match slow.join_timeout(Duration::from_secs(5)) {
+                Ok(value) => println!("join_timeout: {:?}", value.ok()),
+                Err(_) => println!("join_timeout: timed out"),
+            }
+        }
+    }
+
+    // A job that panics no longer takes its worker down with it
+    // The panic payload comes back to us through join() instead
+    let bad = pool.spawn(|| -> u32 { panic!("something went wrong") });
+    match bad.join() {
+        Ok(value) => println!("Bad job returned {}", value),
+        Err(TaskError::Dropped) => println!("Bad job never ran"),
+        Err(TaskError::Panicked(payload)) => {
+            // The payload is a Box<dyn Any>, so we have to guess its type to print it
+            // panic!("literal") gives a &str, panic!("{}", x) gives a String
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+                .unwrap_or("unknown panic");
+            println!("Bad job panicked: {}", message);
+        }
+    }
+
+    // The pool still has all 4 workers, so these still run
+    let after: Vec<u32> = (0..4).map(|n| pool.spawn(move || n + 1)).map(|h| h.join().unwrap()).collect();
+    println!("Jobs after the panic: {:?}", after);
+
+    // The counters are bumped by the worker right after it hands the result to the slot
+    // So we give the workers a moment to catch up before reading them
+    thread::sleep(Duration::from_millis(50));
+    println!("Completed: {}, Panicked: {}", pool.completed(), pool.panicked_count());
+
+    pool.join();
+
+    // A pool that grows and shrinks with the workload
+    let pool = ThreadPoolBuilder::new()
+        .set_min_threads(1)
+        .set_max_threads(4)
+        .keep_alive(Duration::from_millis(200))
+        .build();
+    println!("Elastic pool starts with {} thread(s)", pool.num_threads());
+
+    // A burst of slow jobs backs up the queue, so the pool grows to max_threads
+    for i in 0..8 {
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(100));
+            println!("Burst job {} complete", i);
+        });
+    }
+    println!("During the burst: {} thread(s)", pool.num_threads());
+
+    // Once the burst is done and keep_alive has passed, the extra workers retire
+    thread::sleep(Duration::from_millis(800));
+    println!("After idling: {} thread(s)", pool.num_threads());
+
+    // resize() at runtime
+    // Growing spawns the workers right away
+    pool.resize(3);
+    println!("After resize(3): {} thread(s)", pool.num_threads());
+
+    // Shrinking while jobs are still queued - the extra workers retire between jobs and nothing is dropped
+    for i in 0..6 {
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(50));
+            println!("Resize job {} complete", i);
+        });
+    }
+    pool.resize(1);
+
+    // join() still runs every queued job before returning
     pool.join();
+
+    // Priorities: one worker, kept busy so the other jobs pile up in the queue
+    let pool = ThreadPoolBuilder::new()
+        .set_min_threads(1)
+        .set_max_threads(1)
+        .aging(Duration::from_millis(100))
+        .build();
+    pool.execute(|| thread::sleep(Duration::from_millis(100)));
+
+    // A pile of batch jobs followed by one health check
+    // The health check runs as soon as the worker is free, ahead of all of the batch jobs
+    for i in 0..5 {
+        pool.execute_with_priority(Priority::Low, move || println!("Batch job {}", i));
+    }
+    pool.execute_with_priority(Priority::Critical, || println!("Health check"));
+    thread::sleep(Duration::from_millis(300));
+
+    // Aging: a Low job that has waited more than 3 aging steps beats a High job queued just now
+    pool.execute(|| thread::sleep(Duration::from_millis(500)));
+    pool.execute_with_priority(Priority::Low, || println!("Old low priority job"));
+    thread::sleep(Duration::from_millis(400));
+    pool.execute_with_priority(Priority::High, || println!("New high priority job"));
+
+    pool.join();
+
+    // Graceful shutdown with a timeout: 2 workers, 6 jobs of 200ms each = 600ms of work, but we only wait 300ms
+    let pool = ThreadPool::new(2);
+    for i in 0..6 {
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(200));
+            println!("Graceful job {} complete", i);
+        });
+    }
+    match pool.shutdown_graceful(Duration::from_millis(300)) {
+        Ok(()) => println!("Everything finished in time"),
+        Err(report) => {
+            println!(
+                "Timed out with {} job(s) still running and {} that never started",
+                report.running,
+                report.not_started.len()
+            );
+            // The running jobs finish after shutdown_graceful() returned, but we can still wait for their threads
+            report.join_workers();
+            println!("Remaining workers joined");
+        }
+    }
+
+    // Immediate shutdown: the queued jobs come back to us instead of running
+    let pool = ThreadPool::new(1);
+    for i in 0..5 {
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(100));
+            println!("Shutdown-now job {} complete", i);
+        });
+    }
+    thread::sleep(Duration::from_millis(50));
+    // A job with a TaskHandle, queued behind the others
+    let never_runs = pool.spawn(|| 42);
+    let mut pending = pool.shutdown_now();
+    println!("shutdown_now() returned {} pending job(s)", pending.len());
+
+    // Dropping the spawned job (the last one) closes its slot, so join() returns instead of waiting forever
+    drop(pending.pop());
+    match never_runs.join() {
+        Ok(value) => println!("Spawned job returned {}", value),
+        Err(error) => println!("Spawned job: {:?}", error),
+    }
+
+    // They are ordinary closures, so we can still run them ourselves if we want to
+    for job in pending {
+        job();
+    }
+
+    // Scoped jobs borrow local data instead of owning it
+    let pool = ThreadPool::new(4);
+    let numbers: Vec<i32> = (1..=100).collect();
+    println!("Pooled parallel_sum: {} (expected 5050)", parallel_sum(&pool, &numbers, 4));
+
+    let words = vec!["rust", "parallel", "scoped", "pool"];
+    let uppercase = parallel_map(&pool, &words, 2, |word| word.to_uppercase());
+    println!("Pooled parallel_map: {:?}", uppercase);
+
+    // The same pool is reused for both calls - no new threads were spawned
+    pool.join();
+
+    // Metrics: a mix of fast and slow jobs, then a snapshot in the middle and at the end
+    let pool = ThreadPool::new(2);
+    for i in 0..8 {
+        pool.execute(move || thread::sleep(Duration::from_millis(if i % 2 == 0 { 5 } else { 50 })));
+    }
+    thread::sleep(Duration::from_millis(20));
+    let metrics = pool.metrics();
+    println!(
+        "Mid-run: {} queued, {}/{} workers busy",
+        metrics.queue_depth, metrics.active_workers, metrics.live_workers
+    );
+
+    thread::sleep(Duration::from_millis(300));
+    let metrics = pool.metrics();
+    for worker in &metrics.workers {
+        println!(
+            "Worker {}: {} jobs, busy {:?}, idle {:?}",
+            worker.id, worker.jobs, worker.busy, worker.idle
+        );
+    }
+    println!("{}", metrics.to_prometheus());
+    pool.join();
+
+    // Cancellation: one worker, kept busy by a long job that polls its token
+    let pool = ThreadPool::new(1);
+    let batch = CancellationToken::new();
+    pool.execute_with_token(&batch, |token| {
+        for step in 0..20 {
+            if token.is_cancelled() {
+                println!("Long job noticed the cancellation after {} step(s)", step);
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        println!("Long job ran to the end");
+    });
+    // These queue up behind the long job and never get a chance to start
+    for i in 0..5 {
+        pool.execute_with_token(&batch, move |_| println!("Batch job {} ran", i));
+    }
+    // A job with its own token is not affected by cancelling the batch
+    pool.execute_with_token(&CancellationToken::new(), |_| println!("Unrelated job ran"));
+
+    thread::sleep(Duration::from_millis(50));
+    batch.cancel();
+
+    // Deadlines: the first job holds the worker for 100ms, so only the job with the generous deadline makes it
+    pool.execute(|| thread::sleep(Duration::from_millis(100)));
+    let now = Instant::now();
+    pool.execute_with_deadline(now + Duration::from_millis(20), || println!("Tight deadline job ran"));
+    pool.execute_with_deadline(now + Duration::from_secs(5), || println!("Generous deadline job ran"));
+
+    thread::sleep(Duration::from_millis(300));
+    println!(
+        "Completed: {}, Cancelled: {}, Skipped: {}",
+        pool.completed(),
+        pool.cancelled_count(),
+        pool.skipped_count()
+    );
+    pool.join();
+
+    // Dropping the pool waits for its jobs, just like join()
+    {
+        let pool = ThreadPool::new(2);
+        pool.execute(|| {
+            thread::sleep(Duration::from_millis(100));
+            println!("Job finished before the pool was dropped");
+        });
+    } // pool dropped here
 }
 
 // Arc:
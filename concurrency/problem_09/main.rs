@@ -18,13 +18,23 @@
 // All workers must complete each phase before ANY worker can proceed to the next phase
 // Key challenge: Use Barrier to ensure phase synchronization - no workers start phase 2 until ALL workers finish phase 1
 
-use std::sync::{Arc, Barrier, mpsc};
-use std::sync::atomic::{AtomicU32, Ordering}; // Atomic data types are data types that allow safe concurrent access to shared data across multiple threads without using locks
+// std::sync::Barrier needs a fixed count up front - if one worker fails and never calls wait(), everyone else hangs forever
+// So we build our own PhasedBarrier (like Java's Phaser) where workers can join and leave between phases
+
+// After the Barrier version, we build the same thing as a reusable Pipeline:
+// Pipeline::new().stage(name, workers, fn).stage(...).run(input)
+// Each stage gets its own worker count, stages are connected by bounded channels,
+// and the stats for every stage are collected for us
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering}; // Atomic data types are data types that allow safe concurrent access to shared data across multiple threads without using locks
 // They rely on hardware-level atomic instructions
 // When multiple threads read and write the same variable, you can get data races
 // Atomic types prevent data races, guarantee indivisible operations, and are faster than mutexes for simple shared state
 use std::thread;
-use std::time::Duration;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use rand::Rng;
 
 struct DataItem {
@@ -34,14 +44,14 @@ struct DataItem {
 }
 
 #[derive(Debug)]
-struct PipelineStats {
+struct PhaseStats {
     phase1_complete: AtomicU32,
     phase2_complete: AtomicU32,
     phase3_complete: AtomicU32,
     total_processed: AtomicU32,
 }
 
-impl PipelineStats {
+impl PhaseStats {
     // Associated function
     // Does not take self as an input parameter
     // Does not need an instance of the struct to work
@@ -56,7 +66,397 @@ impl PipelineStats {
     }
 }
 
-fn main() {
+// -----
+
+// Phased barrier
+
+// A barrier where the number of parties can change:
+// - register(): one more party must arrive before the phase can end
+// - arrive_and_deregister(): "count me as arrived, and don't wait for me in later phases"
+// - wait() / wait_timeout(): arrive and wait for everyone else
+// The phase number goes up by one every time all parties have arrived
+
+// The last party to arrive is the leader for that phase
+// If an on_advance callback is set, the leader runs it before anyone is released (e.g. merge results, print progress)
+// The callback runs while the barrier is locked, so it must not call back into the barrier
+
+struct PhaseState {
+    phase: u64,     // How many phases have completed
+    parties: usize, // How many parties take part in the current phase
+    arrived: usize, // How many of them have arrived
+}
+
+struct PhasedBarrier {
+    state: Mutex<PhaseState>,
+    // Notified every time the phase advances
+    advanced: Condvar,
+    // Called by the leader with the number of the phase that just completed
+    on_advance: Option<Box<dyn Fn(u64) + Send + Sync>>,
+}
+
+// What wait() returns, like std's BarrierWaitResult but with the phase number
+#[derive(Debug, Clone, Copy)]
+struct PhaseWaitResult {
+    phase: u64,      // The phase we waited for
+    is_leader: bool, // True for exactly one party per phase: the one that arrived last
+}
+
+// Returned by wait_timeout() when the others didn't arrive in time
+// arrived counts the party that timed out
+// Afterwards it is taken back out of the arrived count, since it is no longer waiting
+#[derive(Debug)]
+struct WaitTimeout {
+    phase: u64,
+    arrived: usize,
+    parties: usize,
+}
+
+impl fmt::Display for WaitTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timed out waiting for phase {}: {} of {} parties arrived",
+            self.phase, self.arrived, self.parties
+        )
+    }
+}
+
+impl PhasedBarrier {
+    fn new(parties: usize) -> Self {
+        Self {
+            state: Mutex::new(PhaseState {
+                phase: 0,
+                parties,
+                arrived: 0,
+            }),
+            advanced: Condvar::new(),
+            on_advance: None,
+        }
+    }
+
+    // Set the leader callback
+    fn on_advance<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+    {
+        self.on_advance = Some(Box::new(callback));
+        self
+    }
+
+    fn phase(&self) -> u64 {
+        self.state.lock().unwrap().phase
+    }
+
+    fn parties(&self) -> usize {
+        self.state.lock().unwrap().parties
+    }
+
+    // Add a party to the current phase, returns the phase it joins
+    fn register(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.parties += 1;
+        state.phase
+    }
+
+    // Arrive for the current phase and leave the barrier
+    // Never blocks - if we were the last one missing, this ends the phase (and we are the leader)
+    fn arrive_and_deregister(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let phase = state.phase;
+        state.parties = state.parties.saturating_sub(1);
+        if state.parties > 0 && state.arrived == state.parties {
+            self.advance(&mut state);
+        }
+        phase
+    }
+
+    // Arrive and block until every party has arrived
+    fn wait(&self) -> PhaseWaitResult {
+        let mut state = self.state.lock().unwrap();
+        let phase = state.phase;
+        state.arrived += 1;
+        if state.arrived >= state.parties {
+            self.advance(&mut state);
+            return PhaseWaitResult { phase, is_leader: true };
+        }
+        // Loop because of spurious wakeups: only leave once the phase number has moved on
+        while state.phase == phase {
+            state = self.advanced.wait(state).unwrap();
+        }
+        PhaseWaitResult { phase, is_leader: false }
+    }
+
+    // Same as wait(), but give up after timeout
+    fn wait_timeout(&self, timeout: Duration) -> Result<PhaseWaitResult, WaitTimeout> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        let phase = state.phase;
+        state.arrived += 1;
+        if state.arrived >= state.parties {
+            self.advance(&mut state);
+            return Ok(PhaseWaitResult { phase, is_leader: true });
+        }
+        while state.phase == phase {
+            let now = Instant::now();
+            if now >= deadline {
+                // Report what we saw (counting ourselves), then take our arrival back
+                // We are not waiting anymore, so we shouldn't count as arrived
+                let error = WaitTimeout {
+                    phase,
+                    arrived: state.arrived,
+                    parties: state.parties,
+                };
+                state.arrived -= 1;
+                return Err(error);
+            }
+            state = self.advanced.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        Ok(PhaseWaitResult { phase, is_leader: false })
+    }
+
+    // Called with the lock held by whoever completed the phase
+    fn advance(&self, state: &mut PhaseState) {
+        if let Some(callback) = &self.on_advance {
+            callback(state.phase);
+        }
+        state.arrived = 0;
+        state.phase += 1;
+        self.advanced.notify_all();
+    }
+}
+
+// -----
+
+// Pipeline framework
+
+// The Barrier version has a few problems:
+// - The three phases are hardcoded inside one closure
+// - Every phase has the same number of workers (4), even if one phase is much slower than the others
+// - Nobody can start phase 2 until everyone finishes phase 1, so fast workers sit idle at every barrier
+// - Adding a phase means adding another counter to the stats struct and another barrier_clone.wait()
+
+// A pipeline streams items through instead:
+    // input → [fetch x2] → channel → [process x4] → channel → [report x1] → output
+// - Each stage is a function from one item to the next
+// - Each stage has its own workers, all pulling from the same channel
+// - As soon as an item leaves one stage, the next stage can start on it - no waiting for the whole batch
+// - Channels are bounded (sync_channel), so a slow stage makes the stages before it wait (backpressure)
+//   instead of the items piling up in memory
+
+// Stats for one stage, filled in by its workers while the pipeline runs
+#[derive(Debug)]
+struct StageStats {
+    name: String,
+    workers: usize,
+    processed: AtomicU32,  // Items that went through this stage
+    busy_micros: AtomicU64, // Time spent inside the stage function, summed over all workers
+}
+
+impl StageStats {
+    fn new(name: &str, workers: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            workers,
+            processed: AtomicU32::new(0),
+            busy_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        self.processed.fetch_add(1, Ordering::SeqCst);
+        self.busy_micros.fetch_add(elapsed.as_micros() as u64, Ordering::SeqCst);
+    }
+}
+
+// One StageStats per stage, in pipeline order
+// Made automatically by Pipeline::stage(), so adding a stage never means touching this struct
+#[derive(Debug)]
+struct PipelineStats {
+    stages: Vec<Arc<StageStats>>,
+}
+
+impl PipelineStats {
+    // Items that made it out of the last stage
+    fn total_processed(&self) -> u32 {
+        self.stages
+            .last()
+            .map_or(0, |stage| stage.processed.load(Ordering::SeqCst))
+    }
+}
+
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<10} {:>7} {:>9} {:>9} {:>12}", "stage", "workers", "processed", "busy ms", "avg µs/item")?;
+        for stage in &self.stages {
+            let processed = stage.processed.load(Ordering::SeqCst);
+            let busy = stage.busy_micros.load(Ordering::SeqCst);
+            let average = if processed == 0 { 0 } else { busy / processed as u64 };
+            writeln!(
+                f,
+                "{:<10} {:>7} {:>9} {:>9} {:>12}",
+                stage.name,
+                stage.workers,
+                processed,
+                busy / 1000,
+                average
+            )?;
+        }
+        write!(f, "total processed: {}", self.total_processed())
+    }
+}
+
+// Channel capacity between stages when Pipeline::capacity() isn't called
+const DEFAULT_CHANNEL_CAPACITY: usize = 16;
+
+// The stages are stored as one function that, given the receiver feeding the first stage,
+// spawns every stage's workers and returns the receiver coming out of the last stage
+// Each call to stage() wraps the previous function, so the types line up at compile time:
+// Pipeline<I, O> takes I in and gives O out, and stage(f: O -> N) turns it into Pipeline<I, N>
+type SpawnStages<I, O> = Box<dyn FnOnce(mpsc::Receiver<I>, usize, &mut Vec<JoinHandle<()>>) -> mpsc::Receiver<O>>;
+
+struct Pipeline<I, O> {
+    spawn_stages: SpawnStages<I, O>,
+    stats: Vec<Arc<StageStats>>,
+    capacity: usize,
+}
+
+impl<T: Send + 'static> Pipeline<T, T> {
+    // An empty pipeline passes its input straight through
+    fn new() -> Self {
+        Self {
+            spawn_stages: Box::new(|input, _, _| input),
+            stats: Vec::new(),
+            capacity: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+}
+
+impl<I: Send + 'static, O: Send + 'static> Pipeline<I, O> {
+    // How many items each channel between stages can hold before the sender blocks
+    fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    // Add a stage with `workers` threads, each running `func` on the items coming out of the previous stage
+    // func is shared by all the stage's workers, so it must be Fn + Sync (not FnMut)
+    fn stage<N, F>(self, name: &str, workers: usize, func: F) -> Pipeline<I, N>
+    where
+        N: Send + 'static,
+        F: Fn(O) -> N + Send + Sync + 'static,
+    {
+        let stage_stats = Arc::new(StageStats::new(name, workers.max(1)));
+        let mut stats = self.stats;
+        stats.push(Arc::clone(&stage_stats));
+
+        let previous = self.spawn_stages;
+        let func = Arc::new(func);
+        let name = name.to_string();
+
+        let spawn_stages: SpawnStages<I, N> = Box::new(move |input, capacity, handles| {
+            // Start everything before us first, and take the receiver coming out of it
+            // mpsc::Receiver can only be used by one thread at a time, so the workers share it through a Mutex
+            let incoming = Arc::new(Mutex::new(previous(input, capacity, handles)));
+            let (tx, outgoing) = mpsc::sync_channel(capacity);
+
+            for worker_id in 0..stage_stats.workers {
+                let incoming = Arc::clone(&incoming);
+                let tx = tx.clone();
+                let func = Arc::clone(&func);
+                let stage_stats = Arc::clone(&stage_stats);
+                let handle = thread::Builder::new()
+                    .name(format!("{}-{}", name, worker_id))
+                    .spawn(move || loop {
+                        // The lock guard is a temporary, so it is released at the end of this statement
+                        // That way only the recv() is serialized, not the work itself
+                        let item = match incoming.lock().unwrap().recv() {
+                            Ok(item) => item,
+                            // The previous stage is done and its channel is empty
+                            Err(_) => break,
+                        };
+                        let started = Instant::now();
+                        let result = func(item);
+                        stage_stats.record(started.elapsed());
+                        // The next stage only goes away if it panicked, nothing left to do then
+                        if tx.send(result).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("failed to spawn pipeline worker");
+                handles.push(handle);
+            }
+            // Our own tx is dropped here, so once every worker exits the next stage sees the channel close
+            outgoing
+        });
+
+        Pipeline {
+            spawn_stages,
+            stats,
+            capacity: self.capacity,
+        }
+    }
+
+    // Push every input item through all the stages and collect what comes out of the last one
+    // With more than one worker per stage, the output order is not the input order
+    fn run<Input: IntoIterator<Item = I>>(self, input: Input) -> (Vec<O>, PipelineStats) {
+        let (tx, rx) = mpsc::sync_channel(self.capacity);
+        let mut handles = Vec::new();
+        let output = (self.spawn_stages)(rx, self.capacity, &mut handles);
+
+        // Collect on another thread: if we fed and collected on this thread, a full output channel
+        // would block the last stage, which blocks every stage before it, which blocks our send - deadlock
+        let collector = thread::spawn(move || output.into_iter().collect::<Vec<O>>());
+
+        for item in input {
+            if tx.send(item).is_err() {
+                break;
+            }
+        }
+        // Closing the input channel lets the shutdown ripple through the stages one after another
+        drop(tx);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let results = collector.join().unwrap();
+        (results, PipelineStats { stages: self.stats })
+    }
+}
+
+// The same fetch → process → aggregate job as the Barrier version, built with Pipeline
+fn pipeline_demo() {
+    println!("\n=== Pipeline ===");
+
+    let (results, stats) = Pipeline::new()
+        .capacity(8)
+        // Fetch: turn an id into a DataItem with a random raw value
+        .stage("fetch", 2, |id: u32| {
+            thread::sleep(Duration::from_millis(2));
+            DataItem {
+                id,
+                raw_value: rand::rng().random_range(1..100),
+                processed_value: None,
+            }
+        })
+        // Process: the slow step, so it gets the most workers
+        .stage("process", 4, |mut item: DataItem| {
+            thread::sleep(Duration::from_millis(10));
+            item.processed_value = Some(item.raw_value * 2);
+            item
+        })
+        // Aggregate: a single worker that reduces each item to the number we sum up
+        .stage("aggregate", 1, |item: DataItem| {
+            (item.id, item.processed_value.unwrap_or(0))
+        })
+        .run(0..40);
+
+    let total: u32 = results.iter().map(|(_, value)| value).sum();
+    println!("Received {} items, sum of processed values: {}", results.len(), total);
+    println!("{}", stats);
+}
+
+// The hand-rolled version: three phases in one closure, separated by barriers
+fn barrier_phases() {
 
     // This demonstrates the PipelinePattern:
     // - Multiple workers process data in synchronized phases
@@ -71,10 +471,14 @@ fn main() {
     // Arc allows multiple owners of the same barrier
     // Each thread gets a pointer (Arc clone) to the same Barrier
     // When threads call .wait() on the same barrier, it releases them all
-    let barrier = Arc::new(Barrier::new(4));
+    // The leader (last to arrive) prints a line before everyone is released
+    let barrier = Arc::new(PhasedBarrier::new(4).on_advance(|phase| {
+        println!("--- Phase {} complete ---", phase + 1);
+    }));
 
-    // Note: The number you pass to Barrier::new(N) must match the number of threads that will call .wait() on it
+    // Note: With std::sync::Barrier, the number you pass to Barrier::new(N) must match the number of threads that will call .wait() on it
     // If mismatched, threads will deadlock (hang forever)
+    // PhasedBarrier lets a worker leave with arrive_and_deregister(), so the count can go down mid-run (worker 3 does this below)
     // The barrier resets after each synchronization, so it can be resused for multiple phases
 
     // When the 4th (last) threads calls .wait(), ALL threads are released simultaneously
@@ -83,7 +487,7 @@ fn main() {
     // This does not need to mutable since AtomicU32 provides interior mutability - you can mutate through a shared reference
     // It can be modified through &self using atomic CPU instructions
     // We need to wrap this in Arc since it is not globally available
-    let stats = Arc::new(PipelineStats::new());
+    let stats = Arc::new(PhaseStats::new());
 
     // We are creating a transmitter and receiver to send messages through
     // There can be multiple transmitters (can clone them) but only one receiver 
@@ -160,6 +564,15 @@ fn main() {
             // Start phase 2
             println!("Worker {}: Phase 2 starting", worker_id);
 
+            // Simulate a worker failing: it drops out instead of finishing its items
+            // With std::sync::Barrier the other 3 workers would wait for it at the end of phase 2 forever
+            // arrive_and_deregister() counts it as arrived for this phase and removes it from the later ones
+            if worker_id == 3 {
+                println!("Worker {}: Phase 2 failed, dropping out", worker_id);
+                barrier_clone.arrive_and_deregister();
+                return;
+            }
+
             // We are iterating over a vector of DataItem structs
             for item in &mut items {
                 // Simulate processing time
@@ -239,3 +652,49 @@ fn main() {
     // All threads have finished (joined) so there's no more concurrent access anyway
     println!("Final stats {:?}", stats);
 }
+
+// register(), the leader flag and wait_timeout() on their own
+fn phased_barrier_demo() {
+    println!("\n=== PhasedBarrier ===");
+
+    let barrier = Arc::new(PhasedBarrier::new(1));
+    let mut handles = Vec::new();
+
+    // Two more workers join after the barrier was created
+    for worker_id in 1..=2 {
+        let joined_phase = barrier.register();
+        println!("Worker {} registered for phase {}", worker_id, joined_phase);
+        let barrier = Arc::clone(&barrier);
+        handles.push(thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20 * worker_id));
+            let result = barrier.wait();
+            if result.is_leader {
+                println!("Worker {} arrived last in phase {} (leader)", worker_id, result.phase);
+            }
+            // Done for good - leave, so later phases don't wait for us
+            barrier.arrive_and_deregister();
+        }));
+    }
+    let result = barrier.wait();
+    if result.is_leader {
+        println!("Main arrived last in phase {} (leader)", result.phase);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    println!("Phase is now {} with {} parties", barrier.phase(), barrier.parties());
+
+    // A party that never shows up: wait_timeout returns an error instead of hanging
+    let late = barrier.register();
+    println!("Registered a party for phase {} that never arrives", late);
+    match barrier.wait_timeout(Duration::from_millis(100)) {
+        Ok(result) => println!("Phase {} completed", result.phase),
+        Err(timeout) => println!("Main: {}", timeout),
+    }
+}
+
+fn main() {
+    barrier_phases();
+    phased_barrier_demo();
+    pipeline_demo();
+}
@@ -6,7 +6,8 @@
 // - Worker pool pattern (spawn once, reuse threads)
 // - Shared task queue with Arc<Mutex<VecDeque<T>>>
 // - Tracking task completion with atomics
-// - Graceful shutdown (poison pill pattern)
+// - Blocking until work arrives with a Condvar (instead of polling with sleeps)
+// - Graceful shutdown by closing the queue
 // - This is what libraries like Rayon/Tokio do
 
 // The pattern:
@@ -20,31 +21,84 @@
 // 3. Workers process tasks concurrently
 // 4. Track how many tasks each worker completes
 // 5. Gracefully shut down all workers when the queue is empty 
+// 6. Skip tasks that were cancelled or whose deadline passed while they were waiting in the queue
 
 #[allow(dead_code)]
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread::JoinHandle;
 
 #[derive(Debug, Clone)]
 enum Task {
     Process { id: usize, value: i32}, // struct-like variant
-    Shutdown, // Special task to tell workers to exit
+    // There used to be a Shutdown variant here: a "poison pill" that told one worker to exit
+    // close() replaced it (see TaskQueue), so a Task is now always real work
 }
 
-struct TaskQueue {
+// Same CancellationToken as problem 8: clones share one AtomicBool, so cancelling any clone cancels them all
+#[derive(Clone, Default)]
+struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// What actually sits in the queue: the task plus the reasons it might no longer be wanted
+// Workers never see this - get_task() unwraps it and only hands out tasks that should still run
+struct QueuedTask {
+    task: Task,
+    token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl QueuedTask {
+    fn new(task: Task) -> Self {
+        Self { task, token: None, deadline: None }
+    }
+}
+
+// Everything behind the queue's lock
+// closed lives next to the tasks (instead of in its own AtomicBool) since a worker has to check both at the same moment:
+// "queue is empty AND nobody will add more" is the only time it is allowed to exit
+struct QueueState {
     // VecDeque is a double-ended queue from Rust's standard library
     // It is a growable ring buffer that lets you efficiently: 
     // Push front and back
     // Pop front and back
     // VecDeque is ideal for: task queues, schedulers, and producer/consume patterns
-    // We wrap it in Mutex so multiple threads can share tasks, but only one thread can modify/read at a time
-    tasks: Mutex<VecDeque<Task>>, // This is a VecDeque of Task wrapped in Mutex
+    tasks: VecDeque<QueuedTask>,
+    // Set by close() - no more tasks are coming
+    closed: bool,
+}
+
+struct TaskQueue {
+    // We wrap the state in Mutex so multiple threads can share tasks, but only one thread can modify/read at a time
+    state: Mutex<QueueState>,
+    // Workers sleep on this while the queue is empty
+    // add_task() wakes one of them, close() wakes all of them
+    // A Condvar always goes together with a Mutex: wait() releases the lock while sleeping and takes it back before returning
+    available: Condvar,
     // We do not need Arc here since it is dereferenced automatically and it allows it to accept Arc<Mutex>> and Mutex<>
     total_completed: AtomicUsize,
+    // Tasks get_task() threw away instead of handing out
+    total_cancelled: AtomicUsize,
+    total_skipped: AtomicUsize,
 }
 // VecDeque = Vector Double-Ended Queue
 // It is like Vec but you can efficiently add/remove from both ends (front and back)
@@ -62,11 +116,14 @@ impl TaskQueue {
     // Creates an instance of the struct
     fn new() -> Self {
         Self {
-            tasks: Mutex::new(VecDeque::new()),
+            state: Mutex::new(QueueState { tasks: VecDeque::new(), closed: false }),
+            available: Condvar::new(),
             // AtomicUsize provides interior mutability - can be modified through &self
             // No need for Mutex here since atomic operations are inherently thread safe
             // Multiple threads can safely increment this counter concurrently
             total_completed: AtomicUsize::new(0),
+            total_cancelled: AtomicUsize::new(0),
+            total_skipped: AtomicUsize::new(0),
         }
     }
 
@@ -79,20 +136,101 @@ impl TaskQueue {
         // Lock releases when the statement ends
         // Before: [Task1, Task2, Task3]
         // After:  [Task1, Task2, Task3, NewTask] ← added to back
-        self.tasks.lock().unwrap().push_back(task);
+        self.push(QueuedTask::new(task));
+    }
+
+    // Like add_task(), but get_task() skips the task if token has been cancelled by the time a worker reaches it
+    fn add_task_with_token(&self, task: Task, token: &CancellationToken) {
+        self.push(QueuedTask { token: Some(token.clone()), ..QueuedTask::new(task) });
+    }
+
+    // Like add_task(), but get_task() skips the task if a worker only reaches it after deadline
+    fn add_task_with_deadline(&self, task: Task, deadline: Instant) {
+        self.push(QueuedTask { deadline: Some(deadline), ..QueuedTask::new(task) });
+    }
+
+    // All of the add_task*() methods end up here
+    fn push(&self, queued: QueuedTask) {
+        let mut state = self.state.lock().unwrap();
+        // The workers may already have exited, so the task would never run
+        // That's a bug in the caller, so we panic instead of losing the task quietly
+        assert!(!state.closed, "task added after the queue was closed");
+        state.tasks.push_back(queued);
+        drop(state);
+        // Wake up one sleeping worker (if there is one) to take the task
+        // We notify after releasing the lock, so the worker doesn't wake up just to block on the Mutex again
+        self.available.notify_one();
+    }
+
+    // Tells the workers that no more tasks are coming
+    // They still finish whatever is left in the queue, then get_task() returns None and they exit
+    // This replaces the poison pills: one call wakes every worker, so the producer no longer needs to know how many workers there are
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        // Every sleeping worker has to wake up and see closed, not just one
+        self.available.notify_all();
     }
 
     // The worker threads will be calling this to get tasks from the queue
+    // Blocks until there is a task
+    // Returns None only once the queue is closed AND empty - that is the worker's signal to exit
     fn get_task(&self) -> Option<Task> {
-        // .pop_front() removes and returns the task from the front of the queue
-        // Returns Some(task) if the queue has items
-        // None if queue is empty
-        // Lock releases when the statement ends
-        // Before: [Task1, Task2, Task3, Task4]
-        // After:  [Task2, Task3, Task4]
-        // Returns Some(Task1)
-        // If empty []: Returns None
-        self.tasks.lock().unwrap().pop_front()
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(task) = self.pop_runnable(&mut state) {
+                return Some(task);
+            }
+            if state.closed {
+                return None;
+            }
+            // Queue is empty but more tasks may come - sleep until add_task() or close() wakes us
+            // We loop afterwards because Condvars can wake up spuriously (without anybody calling notify)
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    // Like get_task(), but gives up after timeout
+    // Uses the same errors as mpsc::Receiver::recv_timeout(), since a closed queue is like a channel with no senders left:
+    // Timeout = nothing arrived in time, Disconnected = the queue is closed and empty
+    fn get_task_timeout(&self, timeout: Duration) -> Result<Task, RecvTimeoutError> {
+        // A deadline instead of the timeout itself, so a spurious wakeup doesn't restart the clock
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(task) = self.pop_runnable(&mut state) {
+                return Ok(task);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.available.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    // .pop_front() removes and returns the task from the front of the queue
+    // Before: [Task1, Task2, Task3, Task4]
+    // After:  [Task2, Task3, Task4]
+    // Returns Some(Task1)
+    // If empty []: Returns None
+    // Cancelled and expired tasks are counted and dropped, and we keep popping until we find one worth running
+    // The caller holds the lock for the whole loop, so two workers can never both count the same task
+    fn pop_runnable(&self, state: &mut QueueState) -> Option<Task> {
+        while let Some(queued) = state.tasks.pop_front() {
+            if queued.token.as_ref().is_some_and(|token| token.is_cancelled()) {
+                self.total_cancelled.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
+            if queued.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.total_skipped.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
+            return Some(queued.task);
+        }
+        None
     }
 
     fn mark_completed(&self) {
@@ -102,6 +240,14 @@ impl TaskQueue {
     fn completed_count(&self) -> usize {
         self.total_completed.load(Ordering::SeqCst)
     }
+
+    fn cancelled_count(&self) -> usize {
+        self.total_cancelled.load(Ordering::SeqCst)
+    }
+
+    fn skipped_count(&self) -> usize {
+        self.total_skipped.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -119,7 +265,6 @@ fn process_task(task: &Task) {
             thread::sleep(Duration::from_millis(sleep_time));
             println!("  Processed task {} (value={})", id, value);
         }
-        Task::Shutdown => {}
     }
 }
 
@@ -134,8 +279,18 @@ fn main() {
     // this is just the main thread acquiring the lock over and over again (20 times in a row)
     // No threads can interfere since worker threads haven't spawned yet
     println!("Adding 20 tasks to queue...");
+    // Tasks 10-14 belong to a batch we will cancel once the workers are running
+    // Tasks 15-19 are only worth doing in the first 300ms
+    // Every task takes 200ms and there are 4 workers, so the workers won't reach them in time
+    let batch = CancellationToken::new();
+    let deadline = Instant::now() + Duration::from_millis(300);
     for i in 0..20 {
-        queue.add_task(Task::Process { id: i, value: i as i32 * 3});
+        let task = Task::Process { id: i, value: i as i32 * 3};
+        match i {
+            10..=14 => queue.add_task_with_token(task, &batch),
+            15..=19 => queue.add_task_with_deadline(task, deadline),
+            _ => queue.add_task(task),
+        }
     }
     // Now we will have a Mutex<VecDeque> of 20 Task structs
     // Everything will be added from the back for FIFO due to .push_back()
@@ -145,7 +300,7 @@ fn main() {
     // Handles are a way of interacting with spawned threads
     // Workers immediately start pulling tasks as soon as they spawn
     // At this point, only workers compete for the queue lock
-    // Main thread will briefly compete again when adding Shutdown tasks
+    // Main thread will briefly compete again when it calls close()
     let mut handles: Vec<JoinHandle<WorkerStats>> = Vec::new();
 
     for worker_id in 0..4 {
@@ -165,85 +320,45 @@ fn main() {
             let mut completed: usize = 0;
 
             // We are using a loop since we don't know how many tasks the thread will receive from the shared queue
-            // The loop continues indefinitely until:
-            // 1. A Shutdown task is received (break exits the loop)
-            // 2. The thread has processed all its assigned work
-            loop {
-                // We need to store the result of .get_task() in a variable then check it
-                // This is because .pop_front() removes the task from the queue
-                // If we call it twice in a match statement, the first call gets the first task and removes it
-                // The second call would pull from a different or empty queue
-                let task = queue_clone.get_task();
-                // .get_task() acquires the lock here and it is dropped at the semi-colon
-                // So then another thread can get a Task for the queue
-                // Minimal lock scope
-
-                // Rather than using a nested match statement, we are matching on different variants of Some()
-                // This is shorter than using a nested match statement
-                // When we have Option<Task>, we can match on the nested structure in one step
-                match task {
-                    // This will match if the Option is Some AND the inner Task is the Process variant
-                    // It "looks inside" the option in one pattern
-                    Some(t @ Task::Process { id: _, value: _ }) => {
-                        // The @ operator lets you bind a variable to a pattern while still matching on it
-                        // Without the @ operator, we would not have access to the Task, we matched it but didnt capture it
-                        // When we match on Some(variant), we are checking if the pattern matches but we don't have a variable holding the actual Task, so we need @
-                        // "Match this pattern AND give me a variable that holds the matched value"
-                        process_task(&t);
-                        completed += 1;
-                        queue_clone.mark_completed();
-                    }
-                    // If we do not have any tasks the contain Shutdown, this loop would continue forever
-                    Some(Task::Shutdown) => {
-                        // Break immediately exits the loop
-                        // The worker thread will end after the break
-                        break;
-                    }
-                    None => {
-                        // Queue is temporarily empty - sleep and retry
-                        // This prevents the worker from exiting if the queue is just momentarily empty
-                        // Without this, workers would exit as soon as they see None
-                        // even if more tasks are being added by other threads
-                        // Common pattern for worker pools with unknown workload
-                        thread::sleep(Duration::from_millis(100));
-                        continue;
-                    }
-                }
+            // get_task() blocks while the queue is empty, so there is no polling and no sleeping here
+            // The loop ends when get_task() returns None, which only happens once the queue is closed AND drained
+            // Before the Condvar, get_task() returned None whenever the queue was momentarily empty,
+            // so this loop had to sleep and retry, and a Shutdown task per worker was the only way to stop it
+            while let Some(task) = queue_clone.get_task() {
+                // .get_task() held the lock only while taking the task, so other workers can take the next one while we process this one
+                process_task(&task);
+                completed += 1;
+                queue_clone.mark_completed();
             }
 
             // Create a WorkerStats struct after the loop ends
-            // If the thread encounters a shutdown as its first task, then the completed will be 0
-            // Since the loop will break right away and the completed count will not get incremented
+            // If the queue was closed and drained before this worker got anything, completed will be 0
             WorkerStats {
                 worker_id,
                 tasks_completed: completed,
             }
-            // In the thread, the WorkerStats struct will be created after the loop ends
-            // The thread will only end when a Shutdown task is received since, if TaskQueue is None, it sleeps then loops again
         });
 
         // Pushing the handle to the vector so that we can use .join() on them later
         handles.push(handle);
     }
 
-    // Send shutdown signal to all workers
-    // If we did not do this, then the loop in each thread would continue forever
-    println!("\nSending shutdown signals...");
-    // We need 4 in total (one for each worker thread)
-    for _ in 0..4 {
-        queue.add_task(Task::Shutdown);
-    }
-    // This is called the "poison pill" pattern
-    // 1. Producer adds real work to the queue
-    // 2. When done, producer adds "poison pills" (Shutdown tasks)
-    // 3. Workers process real work normally
-    // 4. When a worker gets a poison pill, it exits
+    // The workers are still busy with tasks 0-9, so the batch hasn't started yet
+    thread::sleep(Duration::from_millis(100));
+    println!("\nCancelling tasks 10-14...");
+    batch.cancel();
+
+    // No more tasks are coming
+    // If we did not do this, the workers would block in get_task() forever once the queue is empty
+    // The tasks already in the queue still run - close() only stops the workers from waiting for more
+    println!("\nClosing the queue...");
+    queue.close();
 
     // Waiting for all workers and collect states
     println!("Waiting for workers to finish...\n");
     let mut all_stats = Vec::new();
 
-    // Here, we will wait for each worker thread to finish (they finish when they get Shutdown)
+    // Here, we will wait for each worker thread to finish (they finish once the closed queue is empty)
     // Collects the WorkerStats struct each thread returns
     // Stores them in the all_stats vector
     for handle in handles {
@@ -257,10 +372,42 @@ fn main() {
     }
 
     println!("Total completed: {}", queue.completed_count());
+    println!("Cancelled before starting: {}", queue.cancelled_count());
+    println!("Skipped after their deadline: {}", queue.skipped_count());
+
+    // get_task_timeout() for a consumer that has other things to do than wait
+    println!("\nget_task_timeout()...");
+    let queue = Arc::new(TaskQueue::new());
+
+    // Nothing in the queue yet
+    match queue.get_task_timeout(Duration::from_millis(50)) {
+        Ok(task) => println!("Got {:?}", task),
+        Err(error) => println!("Empty queue: {:?}", error),
+    }
+
+    // A producer adds a task while we are waiting - we are woken up as soon as it arrives, not when the timeout runs out
+    let producer_queue = Arc::clone(&queue);
+    let producer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        producer_queue.add_task(Task::Process { id: 100, value: 1 });
+        producer_queue.close();
+    });
+    let started = Instant::now();
+    match queue.get_task_timeout(Duration::from_secs(5)) {
+        Ok(task) => println!("Got {:?} after {:?}", task, started.elapsed()),
+        Err(error) => println!("No task: {:?}", error),
+    }
+    producer.join().unwrap();
+
+    // Closed and drained - Disconnected right away instead of waiting out the timeout
+    match queue.get_task_timeout(Duration::from_secs(5)) {
+        Ok(task) => println!("Got {:?}", task),
+        Err(error) => println!("Closed queue: {:?}", error),
+    }
 }
 
 // The main thread does not really compete for the lock
-// It only competes during the brief moment it adds Shutdown tasks
+// It only competes during the brief moment it calls close()
 // Most of the time it is:
 // 1. Adding tasks alone (before workers exist)
 // 2. Idle while workers process
@@ -271,7 +418,8 @@ fn main() {
 // ✅ Shared queue (Arc<Mutex<VecDeque<T>>>)
 // ✅ FIFO processing (first added = first processed)
 // ✅ Atomic counters (lock-free progress tracking)
-// ✅ Graceful shutdown (poison pill pattern)
+// ✅ Blocking without polling (Condvar)
+// ✅ Graceful shutdown (close() instead of poison pills)
 // ✅ Concurrent execution (workers race for tasks)
 //
 // Real-world uses:
@@ -39,7 +39,17 @@
 // Without backpressure: Producer generates 1000 logs -> all get queued immediately -> memory explosion
 // With backpressure: Producer slows down when the buffer is full -> controlled memory usage
 
-use std::sync::mpsc;
+// sync_channel uses a mutex internally, so every send and recv takes a lock
+// At the end of this file we build our own bounded buffer without locks - a ring buffer
+// and run the same log scenario on it
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::hint;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvError, SendError};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -51,6 +61,526 @@ struct LogEntry {
     timestamp: Instant,
 }
 
+// -----
+
+// Lock-free ring buffer
+
+// A ring buffer is a fixed-size array that we treat as a circle
+// We keep two counters that only ever go up:
+// - tail: where the next push goes
+// - head: where the next pop comes from
+// The slot for a counter is counter % capacity, so once we reach the end of the array we wrap around to the start
+
+    // capacity = 5, head = 7, tail = 10 (3 items, pushed at positions 7, 8 and 9)
+    // slot:     0       1       2       3       4
+    //        [empty] [empty]  [7]     [8]     [9]
+    //           ^               ^
+    //           |               head: 7 % 5 = 2 (next pop)
+    //           tail: 10 % 5 = 0 (next push wraps around to the start)
+    // len   = tail - head = 3
+    // full  = len == capacity
+    // empty = head == tail
+
+// Because the array never grows, memory is bounded - that is where the backpressure comes from
+// Instead of blocking when the buffer is full, push hands the value back: Err(Full(value))
+// The caller decides what to do - retry, drop the value, or block (see RingSender further down)
+
+// Returned by try_push when there is no free slot
+// It owns the value we couldn't push so the caller gets it back instead of losing it
+struct Full<T>(T);
+
+impl<T> Full<T> {
+    // Take the value back out
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// Like mpsc's TrySendError, we don't require T: Debug
+impl<T> fmt::Debug for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Full(..)")
+    }
+}
+
+impl<T> fmt::Display for Full<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ring buffer is full")
+    }
+}
+
+// SPSC mode (single producer, single consumer) -----
+
+// With exactly one producer and one consumer, nobody competes for the same counter:
+// - only the producer writes tail
+// - only the consumer writes head
+// So there is no CAS and no retry loop - every push and pop finishes in a fixed number of steps (wait-free)
+// The types enforce the "single": SpscProducer and SpscConsumer are not Clone
+
+struct SpscRing<T> {
+    // UnsafeCell because the producer writes into slots while the consumer reads other slots
+    // MaybeUninit because a slot is empty until the producer writes it
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize, // Next slot to pop (written by the consumer only)
+    tail: AtomicUsize, // Next slot to push (written by the producer only)
+}
+
+// The producer and consumer live on different threads and both reach the ring through an Arc
+// Values move from one thread to the other, so T: Send is all we need
+unsafe impl<T: Send> Send for SpscRing<T> {}
+unsafe impl<T: Send> Sync for SpscRing<T> {}
+
+struct SpscProducer<T> {
+    ring: Arc<SpscRing<T>>,
+}
+
+struct SpscConsumer<T> {
+    ring: Arc<SpscRing<T>>,
+}
+
+impl<T> SpscProducer<T> {
+    fn try_push(&self, value: T) -> Result<(), Full<T>> {
+        // Our own counter, nobody else writes it
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        // Acquire pairs with the consumer's Release: once we see head move, the consumer is done with that slot
+        let head = self.ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == self.ring.slots.len() {
+            return Err(Full(value));
+        }
+
+        let slot = &self.ring.slots[tail % self.ring.slots.len()];
+        unsafe {
+            (*slot.get()).write(value);
+        }
+        // Release publishes the value we just wrote before the consumer can see the new tail
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T> SpscConsumer<T> {
+    fn try_pop(&self) -> Option<T> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let slot = &self.ring.slots[head % self.ring.slots.len()];
+        let value = unsafe { (*slot.get()).assume_init_read() };
+        // Hand the slot back to the producer
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+}
+
+// Whatever was pushed but never popped still has to be dropped
+impl<T> Drop for SpscRing<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe {
+                self.slots[head % self.slots.len()].get_mut().assume_init_drop();
+            }
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+// MPMC mode (multi producer, multi consumer) -----
+
+// With several producers, two of them can read the same tail and both try to fill the same slot
+// Dmitry Vyukov's bounded queue solves this by giving every slot its own sequence number:
+// - sequence == position: the slot is empty and ready for the push at that position
+// - sequence == position + 1: the slot is full and ready for the pop at that position
+// - after a pop, sequence = position + capacity: ready for the push one lap later
+
+// A producer claims a position with a CAS on tail, then fills the slot and bumps its sequence
+// A consumer claims a position with a CAS on head, then empties the slot and bumps its sequence
+// Nobody ever waits on a lock - a failed CAS just means someone else claimed that position, so we try the next one
+
+// Counters only go up, so after 2^64 operations they would wrap - not something we need to worry about here
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct RingBuffer<T> {
+    slots: Box<[Slot<T>]>,
+    head: AtomicUsize, // Next position to pop
+    tail: AtomicUsize, // Next position to push
+}
+
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    // MPMC ring buffer, share it with Arc
+    // Capacity 1 does not work with the sequence scheme: after a push at pos 0 the slot's sequence is 1,
+    // which is also "empty and ready for a push at pos 1", so every push would overwrite (and leak) the last one
+    fn new(capacity: usize) -> Self {
+        assert!(capacity >= 2, "MPMC ring buffer capacity must be at least 2");
+        Self {
+            slots: (0..capacity)
+                .map(|i| Slot {
+                    sequence: AtomicUsize::new(i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    // SPSC ring buffer, split into its two ends
+    fn spsc(capacity: usize) -> (SpscProducer<T>, SpscConsumer<T>) {
+        assert!(capacity > 0, "ring buffer capacity must be at least 1");
+        let ring = Arc::new(SpscRing {
+            slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        });
+        (
+            SpscProducer { ring: Arc::clone(&ring) },
+            SpscConsumer { ring },
+        )
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // Approximate number of values (exact when nobody is pushing or popping)
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.saturating_sub(head).min(self.capacity())
+    }
+
+    fn try_push(&self, value: T) -> Result<(), Full<T>> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.capacity()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            // How far the slot is from being ready for a push at pos
+            let diff = sequence.wrapping_sub(pos) as isize;
+
+            if diff == 0 {
+                // Slot is empty and it's our turn - claim the position
+                match self.tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe {
+                            (*slot.value.get()).write(value);
+                        }
+                        // Release publishes the value to the consumer that will wait for pos + 1
+                        slot.sequence.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    // Another producer took pos, try the position it left us
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // The slot still holds the value from one lap ago: the buffer is full
+                return Err(Full(value));
+            } else {
+                // Another producer already moved past pos, catch up
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % self.capacity()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence.wrapping_sub(pos + 1) as isize;
+
+            if diff == 0 {
+                // Slot is full and it's our turn
+                match self.head.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        // Mark the slot empty for the push one lap later
+                        slot.sequence.store(pos + self.capacity(), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // Nothing has been pushed at pos yet: the buffer is empty
+                return None;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+
+// Blocking wrapper -----
+
+// try_push/try_pop never block, but the log demo wants sync_channel's behaviour:
+// send waits while the buffer is full, recv waits while it is empty, and recv ends once every sender is gone
+// We wait with a backoff instead of a lock: spin a little, then yield the CPU to other threads, then sleep briefly
+
+struct Channel<T> {
+    buffer: RingBuffer<T>,
+    senders: AtomicUsize,       // recv returns Err once this hits 0 and the buffer is empty
+    receiver_alive: AtomicBool, // send returns Err once the receiver is dropped
+}
+
+// Same shape as mpsc::SyncSender/Receiver, so it can replace sync_channel in the log demo
+struct RingSender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+struct RingReceiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+// Like sync_channel, except the capacity must be at least 2 (RingBuffer::new asserts this)
+fn ring_channel<T>(capacity: usize) -> (RingSender<T>, RingReceiver<T>) {
+    let channel = Arc::new(Channel {
+        buffer: RingBuffer::new(capacity),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        RingSender { channel: Arc::clone(&channel) },
+        RingReceiver { channel },
+    )
+}
+
+// Wait a little longer every time we are called
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    fn snooze(&mut self) {
+        if self.step < 6 {
+            for _ in 0..(1 << self.step) {
+                hint::spin_loop();
+            }
+        } else if self.step < 10 {
+            thread::yield_now();
+        } else {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.step += 1;
+    }
+}
+
+impl<T> RingSender<T> {
+    // Blocks while the buffer is full (backpressure), like SyncSender::send
+    fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = value;
+        let mut backoff = Backoff::new();
+        loop {
+            if !self.channel.receiver_alive.load(Ordering::Acquire) {
+                return Err(SendError(value));
+            }
+            match self.channel.buffer.try_push(value) {
+                Ok(()) => return Ok(()),
+                Err(full) => {
+                    value = full.into_inner();
+                    backoff.snooze();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Clone for RingSender<T> {
+    fn clone(&self) -> Self {
+        self.channel.senders.fetch_add(1, Ordering::Relaxed);
+        Self { channel: Arc::clone(&self.channel) }
+    }
+}
+
+impl<T> Drop for RingSender<T> {
+    fn drop(&mut self) {
+        self.channel.senders.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> RingReceiver<T> {
+    // Blocks while the buffer is empty, returns Err once it is empty and every sender has been dropped
+    fn recv(&self) -> Result<T, RecvError> {
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(value) = self.channel.buffer.try_pop() {
+                return Ok(value);
+            }
+            if self.channel.senders.load(Ordering::Acquire) == 0 {
+                // A sender might have pushed right before it was dropped, so look one last time
+                return self.channel.buffer.try_pop().ok_or(RecvError);
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T> Drop for RingReceiver<T> {
+    fn drop(&mut self) {
+        self.channel.receiver_alive.store(false, Ordering::Release);
+    }
+}
+
+// Run the ring buffers: non-blocking first, then the log scenario again on the blocking wrapper
+fn ring_buffer_demo() {
+    println!("\n=== Lock-free ring buffer ===");
+
+    // A full buffer hands the value back instead of blocking
+    let buffer = RingBuffer::new(2);
+    buffer.try_push("a").unwrap();
+    buffer.try_push("b").unwrap();
+    match buffer.try_push("c") {
+        Ok(()) => println!("Pushed c"),
+        Err(full) => {
+            println!("try_push(\"c\"): {}", full);
+            println!("The value comes back to us: {:?}", full.into_inner());
+        }
+    }
+    println!("len {} of capacity {}", buffer.len(), buffer.capacity());
+
+    // Capacity 1 is refused up front instead of silently overwriting values (see RingBuffer::new)
+    let rejected = std::panic::catch_unwind(|| RingBuffer::<&str>::new(1)).is_err();
+    println!("RingBuffer::new(1) rejected: {}", rejected);
+
+    // SPSC: one producer, one consumer, values must come out in order
+    let count = 100_000;
+    let (producer, consumer) = RingBuffer::spsc(4);
+    let producer_thread = thread::spawn(move || {
+        let mut full_count = 0;
+        for i in 0..count {
+            let mut value = i;
+            // Spin until there is room - this is the caller's choice, try_push itself never waits
+            while let Err(full) = producer.try_push(value) {
+                value = full.into_inner();
+                full_count += 1;
+                thread::yield_now();
+            }
+        }
+        full_count
+    });
+    let consumer_thread = thread::spawn(move || {
+        let mut expected = 0;
+        while expected < count {
+            match consumer.try_pop() {
+                Some(value) => {
+                    assert_eq!(value, expected, "SPSC ring buffer reordered values");
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        expected
+    });
+    let full_count = producer_thread.join().unwrap();
+    let received = consumer_thread.join().unwrap();
+    println!("SPSC: {} values received in order, producer saw Full {} times", received, full_count);
+
+    // MPMC: 3 producers, 3 consumers, every value must be received exactly once
+    let producers = 3;
+    let consumers = 3;
+    let per_producer = 50_000;
+    let total = producers * per_producer;
+    let buffer = Arc::new(RingBuffer::new(8));
+    let received = Arc::new(AtomicUsize::new(0));
+    let received_sum = Arc::new(AtomicUsize::new(0));
+
+    let mut handles = Vec::new();
+    for p in 0..producers {
+        let buffer = Arc::clone(&buffer);
+        handles.push(thread::spawn(move || {
+            for i in 0..per_producer {
+                let mut value = p * per_producer + i;
+                while let Err(full) = buffer.try_push(value) {
+                    value = full.into_inner();
+                    thread::yield_now();
+                }
+            }
+        }));
+    }
+    for _ in 0..consumers {
+        let buffer = Arc::clone(&buffer);
+        let received = Arc::clone(&received);
+        let received_sum = Arc::clone(&received_sum);
+        handles.push(thread::spawn(move || {
+            while received.load(Ordering::Relaxed) < total {
+                match buffer.try_pop() {
+                    Some(value) => {
+                        received_sum.fetch_add(value, Ordering::Relaxed);
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let expected_sum: usize = (0..total).sum();
+    println!(
+        "MPMC: {} of {} values received, sum matches: {}",
+        received.load(Ordering::Relaxed),
+        total,
+        received_sum.load(Ordering::Relaxed) == expected_sum
+    );
+
+    // The log scenario again, on ring_channel instead of sync_channel
+    // Same 5x speed difference, but 10x shorter sleeps so it doesn't take another 10 seconds
+    println!("\n=== Log processing on ring_channel(5) ===");
+    let (tx, rx) = ring_channel::<LogEntry>(5);
+    let start = Instant::now();
+
+    let producer = thread::spawn(move || {
+        for i in 0..20 {
+            let log = LogEntry {
+                id: i,
+                message: format!("Log entry {}", i),
+                timestamp: Instant::now(),
+            };
+            // Blocks (with backoff, no lock) while all 5 slots are taken
+            tx.send(log).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        println!("Producer: done after {:.2}s", start.elapsed().as_secs_f64());
+        // tx dropped here, so rx.recv() returns Err once the buffer is drained
+    });
+
+    let consumer = thread::spawn(move || {
+        let mut processed = 0;
+        while let Ok(log) = rx.recv() {
+            thread::sleep(Duration::from_millis(50));
+            processed += 1;
+            if log.id % 5 == 4 {
+                println!("  Consumer: finished log {}", log.id);
+            }
+        }
+        processed
+    });
+
+    producer.join().unwrap();
+    let total_processed = consumer.join().unwrap();
+    println!("Total processed: {}", total_processed);
+    println!("Total time: {:.2}s (producer throttled to the consumer's pace again)", start.elapsed().as_secs_f64());
+}
+
 fn main() {
     
     // Creating a bounded channel with a capacity of 5
@@ -200,6 +730,8 @@ fn main() {
     println!("Total time: {:.2}s", elapsed.as_secs_f64());
     println!("\nNote: Producer was throttled by bounded channel!");
     println!("Without backpressure, all 20 logs would queue immediately.");
+
+    ring_buffer_demo();
 }
 
 // Expected behavior with capacity=5 and this timing:
@@ -37,11 +37,11 @@
 // 5. Demonstrate load imbalance and how stealing fixes it
 
 // Scenario:
-// - Create 4 workers
-// - Give Worker 0: 20 slow tasks (200ms each)
-// - Give Worker 1,2,3: 5 fast tasks each (50ms each)
-// - Without stealing: Worker 0 takes forever, others finish quickly and sit idle
-// - With stealing: Workers 1,2,3 steal from Worker 0 -> balanced workload
+// - Create a pool of 4 workers
+// - Submit one job that spawns 20 slow subtasks (200ms each) - they all land on whichever worker ran that job
+// - Submit 15 fast jobs (50ms each) from outside the pool
+// - Without stealing: one worker takes forever, others finish quickly and sit idle
+// - With stealing: the other workers steal the slow subtasks -> balanced workload
 
 // Key concepts:
 // Local work (LIFO): [Task1, Task2, Task3, Task4]
@@ -51,31 +51,58 @@
 // Stealing: Thieves steal from front → gets Task1 (oldest, least likely cache-hot) (FIFO - First In, First Out) - .pop_front()
 // This prevents conflict: worker works on recent tasks, thieves take old tasks
 
-// Per-worker queues: Vec<Arc<Mutex<VecDeque<Task>>>>
-// Steal strategy: Try to steal from (worker_id + 1) % num_workers, then try others
-// Randomization: In production, randomly choose victim to steal from
+// Per-worker queues: one lock-free Chase-Lev deque per worker (see ChaseLevDeque below)
+// Injector: one extra shared queue for jobs submitted from outside the pool
+// Steal strategy: Pick a random victim, then try the others in order from there
+// Randomization: If every thief started at (worker_id + 1), they would all pile onto the same victims in the same order
 
+// The first version of this problem did all of this inline in main() with a hardcoded 0..4 and a Task enum
+// Now it is a reusable WorkStealingPool that takes any closure, so it can be used like the thread pool from problem 8
+
+use rand::Rng;
+use std::cell::{Cell, UnsafeCell};
 use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{self, AtomicBool, AtomicIsize, AtomicPtr, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-#[allow(dead_code)]
-enum Task {
-    Compute { id: usize, workload: u64 }, // workload = milliseconds to sleep
-    Shutdown, // Poison pill to stop workers
-}
+// A job is any closure that can be called once and sent to another thread
+// It gets a &WorkerContext so it can spawn subtasks onto the worker that is running it
+type Job = Box<dyn FnOnce(&WorkerContext) + Send + 'static>;
 
 // Creating a Stats struct to store thread-local statistics
-#[allow(dead_code)]
+// Each worker fills in its own copy without any locking, and they get added together at shutdown
+#[derive(Debug, Default, Clone)]
 struct Stats {
     local_tasks_completed: usize,
     stolen_tasks_completed: usize,
+    injected_tasks_completed: usize,
+    // Jobs that panicked instead of returning - they are not counted as completed
+    tasks_panicked: usize,
+    subtasks_spawned: usize,
     steal_attempts: usize,
     failed_steals: usize,
 }
 
+impl Stats {
+    // Adds another worker's numbers to this one
+    fn merge(&mut self, other: &Stats) {
+        self.local_tasks_completed += other.local_tasks_completed;
+        self.stolen_tasks_completed += other.stolen_tasks_completed;
+        self.injected_tasks_completed += other.injected_tasks_completed;
+        self.tasks_panicked += other.tasks_panicked;
+        self.subtasks_spawned += other.subtasks_spawned;
+        self.steal_attempts += other.steal_attempts;
+        self.failed_steals += other.failed_steals;
+    }
+}
+
 // In problem 40, we had a global TaskQueue
 // tasks: Mutex<VecDeque<Task>>  ← ONE queue, ALL workers compete
 // All workers fight for the SAME lock on the SAME queue
@@ -85,308 +112,725 @@ struct Stats {
 // Each worker has their OWN queue with their OWN lock
 // Workers can steal from OTHER worker's queues when idle
 // Multiple WorkerQueue instances, one per worker
-struct WorkerQueue {
-    tasks: Mutex<VecDeque<Task>>, // Each worker has one of these
+
+// This was the original WorkerQueue
+// One Mutex guards both ends of the deque, so the owner popping from the back and a thief stealing from the front still block each other
+// That defeats a big part of the point of work stealing, so the pool now uses the lock-free ChaseLevDeque below
+// We keep this version around to benchmark against
+struct MutexWorkerQueue<T> {
+    tasks: Mutex<VecDeque<T>>, // Each worker has one of these
 
     // If a field of a struct is wrapped in Mutex, you must .lock() to access it, even if the struct itself isn't wrapped in Mutex
 }
 
-impl WorkerQueue {
+impl<T> MutexWorkerQueue<T> {
     fn new() -> Self {
         Self {
             tasks: Mutex::new(VecDeque::new()),
         }
     }
-    fn push_local(&self, task: Task) {
+    fn push_local(&self, job: T) {
         // Push to back
         // Owner thread of the queue takes from back (LIFO)
         // Threads who steal take from the front
-        self.tasks.lock().unwrap().push_back(task);
+        self.tasks.lock().unwrap().push_back(job);
     }
 
-    fn pop_local(&self) -> Option<Task> {
+    fn pop_local(&self) -> Option<T> {
         // Pop from the back 
         // Owner thread of the queues takes from back (LIFO)
         // Threads who steal take from the front
         self.tasks.lock().unwrap().pop_back()
     }
 
-    fn steal(&self) -> Option<Task> {
+    fn steal(&self) -> Option<T> {
         // Steal from the front 
         // When stealing from another thread, it takes from the other thread's front of the queue
         self.tasks.lock().unwrap().pop_front()
 
-        // We will use it like: let stolen = worker_queues[0].steal();
+        // We will use it like: let stolen = queues[0].steal();
         // Calling .steal() on Worker 0's queue
         // self = Worker 0's queue
         // But Worker 1 is doing the stealing
 
-        // When we steal from Worker 0, we lock Worker 0's queue, pop from the front, and return the Task
-        // The Task VALUE is now in Worker 1's local variable -> From Worker 0's VecDeque to Worker 1's local variable
-        // Worker 0's queue no longer has this task
-        // Worker 1 now owns this Task and executes this task in its own thread
+        // When we steal from Worker 0, we lock Worker 0's queue, pop from the front, and return the Job
+        // The Job VALUE is now in Worker 1's local variable -> From Worker 0's VecDeque to Worker 1's local variable
+        // Worker 0's queue no longer has this job
+        // Worker 1 now owns this Job and executes it in its own thread
 
         // 1. Lock the victim's queue
-        // 2. Remove task from victim's VecDeque
+        // 2. Remove job from victim's VecDeque
         // 3. Unlock the victim's queue 
-        // 4. Return the task value
-        // 5. Thief thread now has the task and processes it
+        // 4. Return the job value
+        // 5. Thief thread now has the job and processes it
     }
 }
 
-fn main() {
+// ===== Chase-Lev deque =====
+
+// The Chase-Lev deque (Chase & Lev, 2005) is the lock-free deque that Rayon, Tokio and Go's scheduler build on
+// It uses the same tools as LockFreeStack in problem 15: raw pointers, atomics, and compare_exchange (CAS)
+// The trick is that the two ends are treated differently:
+// - bottom: only the owner thread ever pushes or pops here, so it mostly doesn't need CAS at all
+// - top: thieves steal from here, and several of them can try at once, so a steal has to win a CAS on top
+// The only real fight is over the very last item, when the owner and a thief both go for it - CAS settles that too
+
+//   top                      bottom
+//    ↓                         ↓
+//   [ A ][ B ][ C ][ D ][    ][    ]
+//   thieves take A         owner pushes/pops at D's end
+
+// top and bottom are ever-increasing indexes (they never wrap), and the actual slot is index % capacity
+// bottom - top = how many items are in the deque
+
+// A fixed-size circular array of slots
+// Capacity is always a power of 2, so index % capacity can be done as index & (capacity - 1), which is faster
+struct Buffer<T> {
+    // UnsafeCell = the one way in Rust to mutate something through a shared & reference (the deque only hands out &self)
+    // MaybeUninit<T> = a slot that may or may not hold a valid T - the compiler won't drop it for us, we track that ourselves
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
 
-    // Creating the worker queues
-    // This gives us worker_queues[0], worker_queues[1], etc.
-    // We are wrapping each in an Arc since they need to be able to be shared across threads (for stealing)
-    // The tasks field for each WorkerQueue is already wrapped in a Mutex, so we will need locks to access them
-    let worker_queues: Vec<Arc<WorkerQueue>> = (0..4) // We don't need .iter() on a range because ranges are already iterators - they implement the Iterator trait directly
-        .map(|_| Arc::new(WorkerQueue::new()))
-        .collect();
-    // Here, we are creating a vector of 4 worker queues using the .map() method and collecting it into a vector
-    // We are putting them all in a vector to make it easier to work with compared to having 4 separate variables
-    // Benefits of Vec: can iterate with loops, can index by worker_id, easy to pass ALL queues to each worker thread
-
-    // Pushing 20 slow tasks to the first worker queue
-    for id in 0..20 {
-        worker_queues[0].push_local(Task::Compute { id, workload: 200 });
-    }
-
-    // Pushing 5 fast tasks to the rest of the worker queues
-    for id in 0..5 {
-        worker_queues[1].push_local(Task::Compute { id, workload: 50 });
-        worker_queues[2].push_local(Task::Compute { id, workload: 50 });
-        worker_queues[3].push_local(Task::Compute { id, workload: 50 });
-    }
-
-    // The above will set up a scenario where Worker 0 is overloaded
-
-    // When spawning threads, we need to wrap the entire Vec in an Arc
-    // The inner arc allows multiple workers to share each individual queue
-    // Needed for stealing: Worker 1 can access Worker 0's queue
-    // The outer Arc is needed to allow multiple workers to share the list of all queues
-    // Without this, can you can't move the Vec into multiple threads
-    let all_queues = Arc::new(worker_queues);
-    // We don't wrap the entire Vec in a Mutex because we are not modifying the vec itself, only the contents of the queues inside it, which are already wrapped in Mutex
-
-    // As a note, you could wrap the entire Vec in Mutex instead of wrapping each WorkerQueue's tasks field
-    // struct WorkerQueue { tasks: VecDeque<Task> }  // No Mutex on field
-    // let all_queues = Arc::new(Mutex::new(vec![...]));  // Mutex on Vec
-    //
-    // But this creates coarse-grained locking where all threads fight for the same lock:
-    // - Worker 0 locks the Vec to access queue[0]
-    // - Workers 1, 2, 3 are BLOCKED, even though they want different queues
-    // - Only ONE worker can access ANY queue at a time
-    // - This defeats the entire purpose of work stealing!
-    //
-    // With fine-grained locking (Mutex on each queue's tasks):
-    // - Worker 0 locks only queue[0].tasks
-    // - Workers 1, 2, 3 can simultaneously lock their own queues
-    // - Workers only contend when actually stealing from the same queue
-    // - Much better performance: workers work in parallel on their own queues
-    //
-    // Coarse-grained: Arc<Mutex<Vec<WorkerQueue>>> → ONE lock, high contention
-    // Fine-grained: Vec<Arc<WorkerQueue { tasks: Mutex<...> }>> → Multiple locks, low contention
-
-    // Wrap the entire Vec in Arc so each worker thread can share access to all queues
-    // Arc<Vec<Arc<WorkerQueue>>> structure:
-    // - Outer Arc: shares the Vec itself across threads
-    // - Inner Arc: shares each individual WorkerQueue across threads (for stealing)
-
-    // Create a vector to store handles
-    // Handles are a way of interacting with spawned threads
-    let mut handles: Vec<JoinHandle<Stats>> = Vec::new();
-
-    // Spawn 4 worker threads
-    for worker_id in 0..4 {
-
-        // Creating a new pointer to the same data
-        // Allows multiple owners of the same Vec of WorkerQueue
-        // This will be moved into each individual thread so it can be used after the loop iteration ends
-        // We need to wrap the entire vector in Arc so that it can be shared across all threads
-        let queue_clone = Arc::clone(&all_queues);
-
-        // Moving the captured variables into the closure (thread) so they can be used once the loop iteration ends
-        let handle = thread::spawn(move || {
-
-            // Creating local variables to track thread-specific statistics
-            // This will ultimately go into the Stats struct
-            let mut local_tasks_completed = 0;
-            let mut stolen_tasks_completed = 0;
-            let mut steal_attempts = 0;
-            let mut failed_steals = 0;
-
-            // Creating a local queue for each thread
-            // The local queue will be the result of indexing the worker queue vector using worker_id
-            // So thread 0 will have queue 0, thread 1 will have queue 1, etc.
-            let local_queue = &queue_clone[worker_id];
-
-            // This loop will be for the local worker and stealing logic
-            // We need to have local work and stealing work in the same loop because we want stealing to happen repeatedly whenever the local queue is empty
-
-            // Worker strategy:
-            // - Local work: pop_back() = LIFO (Last In, First Out) - most recent tasks (cache-hot)
-            // - Stealing: pop_front() = FIFO (First In, First Out) - oldest tasks (less likely cache-hot)
-            // This prevents conflict: worker processes recent work, thieves take old work
-
-            // We are using if let since we only care about the success case (there is a task in the queue to be processed)
-            // We could use a match statement but that is more verbose and unnecessary
-            // If there is an empty queue (no task to be processed), we move onto the next step (trying to steal)
-            // We are using a loop since we DON'T know how many tasks we will get
-            // The worker doesn't know:
-            // - How many tasks are in the local queue initially
-            // - How many tasks it will steal from others
-            // - When the Shutdown signal will arrive
-            'worker_loop: loop { // We are labeling the main loop as worker_loop here so it is explicit we are breaking out of the main loop
-                                // This is especially useful since we will have a nested loop within, so it will be more clear what we are exiting
-                // === Phase 1: Try local work first ===
-                if let Some(task) = local_queue.pop_local() {
-                    match task {
-                        Task::Compute { id, workload } => {
-                            println!("Worker {}: Processed {}. Now sleeping for {}ms.", worker_id, id, workload);
-                            thread::sleep(Duration::from_millis(workload));
-                            local_tasks_completed += 1;
-                        }
-                        // If the task we receive is Shutdown, break the entire loop (the thread will have finished its work)
-                        // This is part of the poison pill pattern
-                        Task::Shutdown => {
-                            println!("Worker {}: Shutting down.", worker_id);
-                            break 'worker_loop;
-                        }
-                    }
-                // We have the main logic and stealing logic inside of the same loop since we want stealing to happen repeatedly whenever the local queue is empty not just once
-                } else {
-                    // === Phase 2: Local empty, try stealing from other workers ===
-
-                    // local queue empty, try stealing
-                    let mut stole_task = false; // Track if we successfully stole anything
-
-                    // If we do not have a task to process, we start looking for victims
-                    for victim_id in 0..4 {
-                        // Since threads should not steal from themselves, we always need to skip them
-                        if victim_id == worker_id {
-                            // continue is used to end this loop iteration and move on to the next one immediately
-                            continue;
-                        }
-                        steal_attempts += 1;
-                        // If .steal() results in Some(task), we bind it to task
-                        // And then process the task
-                        if let Some(task) = queue_clone[victim_id].steal() {
-                            match task {
-                                Task::Compute { id, workload } => {
-                                    println!("Worker {}: Processed {}, stolen from Worker {}. Now sleeping for {}ms.", worker_id, id, victim_id, workload);
-                                    thread::sleep(Duration::from_millis(workload));
-                                    stolen_tasks_completed += 1;
-                                    stole_task = true;
-                                    // Found work, stop trying other victims
-                                    // This will only break the inner (victim) loop
-                                    // After processing a stolen task, you got back to the top of the worker loop and try your local queue again (from the top)
-                                    break;
-                                }
-                                // If the task we receive is Shutdown, break the entire loop (the thread will have finished its work)
-                                Task::Shutdown => {
-                                    println!("Worker {}: Shutting down.", worker_id);
-                                    break 'worker_loop; // We break the entire worker loop not just the victim loop
-                                }
-                            }
-                        } else {
-                            failed_steals += 1;
-
-                            // This continue is redundant since the loop will continue automatically
-                            // We could leave it here just to be explicit
-                            // continue;
-
-                            // We will not sleep here after EACH failed steal 
-                            // We want the thread to briefly sleep after the entire for loop tries all of its victims
-                            // If you put it to sleep after every failed steal, you add unnecessary sleep time for the thread
-                        }
-                    }
-                    // === Phase 3: No work found anywhere, sleep briefly ===
-
-                    // After trying ALL victims, if we didn't steal anything, sleep
-                    if !stole_task {
-                    // Check if ALL queues are empty before sleeping
-
-                        // .all() is an iterator method that checks if ALL elements satisfy a condition
-                        // Returns true if the condition is true for every element
-                        // Returns false if any element fails the condition
-                        // Short-circuits when it finds the first false
-                        // It is iterator.all(|item| condition)
-
-                        // (0..4) creates an iterator
-                        // .all(|i| {...}) checks if the condition is true for all worker IDs, i = current worker ID
-                        // .all() is not lazy - it's a consuming method that executes immediately - there is no separate consumption step
-                        // Iterator methods will iterate over all elements, unless they have short circuiting
-                        // Lazy iterator methods return another iterator, chain together, and don't execute until consumed
-                        // Eager iterator methods return a concrete value (not an iterator) and execute immediately
-                        let all_empty = (0..4).all(|i| {
-                            queue_clone[i].tasks.lock().unwrap().is_empty() // Checking if all tasks vectors are empty for each queue
-                        });
-                        
-                        if all_empty {
-                            println!("Worker {}: All queues empty, exiting", worker_id);
-                            break 'worker_loop;  // Exit when truly no work left
-                        }
-
-                        thread::sleep(Duration::from_millis(10))
-                        // After the thread sleeps for 10 milliseconds, we go back to the start of the worker loop
-                        // Sleep for 10 milliseconds to avoid busy-waiting (hammering CPU)
-                        // Still responsive enough to check for new work frequently;
-                    }
+impl<T> Buffer<T> {
+    // Allocates a buffer on the heap and hands it out as a raw pointer, the same way LockFreeStack allocates nodes
+    fn alloc(capacity: usize) -> *mut Buffer<T> {
+        let slots = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+        Box::into_raw(Box::new(Buffer { slots }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    // SAFETY: only the owner writes, and only to slots that aren't between top and bottom (nobody else reads them)
+    unsafe fn write(&self, index: isize, value: T) {
+        unsafe { ptr::write(self.slot(index), MaybeUninit::new(value)) }
+    }
+
+    // Copies the bits out of a slot without taking ownership yet
+    // A thief can read a slot and then lose the CAS, in which case the copy must be thrown away without being dropped
+    // That is why this returns MaybeUninit<T> instead of T
+    // read_volatile stops the compiler from assuming nobody else can change the slot under us
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        unsafe { ptr::read_volatile(self.slot(index)) }
+    }
+}
+
+struct ChaseLevDeque<T> {
+    top: AtomicIsize,
+    bottom: AtomicIsize,
+    // AtomicPtr since the owner swaps in a bigger buffer when this one fills up
+    buffer: AtomicPtr<Buffer<T>>,
+    // Buffers we grew out of
+    // A thief may have loaded the old pointer just before the swap and still be reading from it, so we can't free it right away
+    // Instead we keep it until the deque itself is dropped (it only happens log2(max size) times, so this stays small)
+    retired: Mutex<Vec<*mut Buffer<T>>>,
+    // Failed steal CAS attempts, same idea as LockFreeStack::retry_count
+    retry_count: AtomicUsize,
+}
+
+// Raw pointers are neither Send nor Sync, so we have to promise the compiler this is safe to share between threads
+// It is, as long as the values themselves can be sent to another thread (T: Send) - that's how they get stolen
+unsafe impl<T: Send> Send for ChaseLevDeque<T> {}
+unsafe impl<T: Send> Sync for ChaseLevDeque<T> {}
+
+const MIN_CAPACITY: usize = 32;
+
+impl<T> ChaseLevDeque<T> {
+    fn new() -> Self {
+        Self {
+            top: AtomicIsize::new(0),
+            bottom: AtomicIsize::new(0),
+            buffer: AtomicPtr::new(Buffer::alloc(MIN_CAPACITY)),
+            retired: Mutex::new(Vec::new()),
+            retry_count: AtomicUsize::new(0),
+        }
+    }
+
+    // Owner only
+    // Copies everything into a buffer twice the size and swaps it in
+    unsafe fn grow(&self, old: *mut Buffer<T>, bottom: isize, top: isize) -> *mut Buffer<T> {
+        unsafe {
+            let new = Buffer::alloc((*old).capacity() * 2);
+            for index in top..bottom {
+                // The bits are copied, not moved, so the old buffer still has a stale copy
+                // That's fine - the old buffer is never dropped slot by slot, only freed
+                (*new).write(index, (*old).read(index).assume_init());
+            }
+            // Release: a thief that sees the new pointer also sees the values we just copied into it
+            self.buffer.store(new, Ordering::Release);
+            self.retired.lock().unwrap().push(old);
+            new
+        }
+    }
+
+    // Owner only: push onto the bottom
+    unsafe fn push(&self, value: T) {
+        let bottom = self.bottom.load(Ordering::Relaxed);
+        let top = self.top.load(Ordering::Acquire);
+        let mut buffer = self.buffer.load(Ordering::Relaxed);
+
+        unsafe {
+            // Full - make room first
+            if bottom - top >= (*buffer).capacity() as isize {
+                buffer = self.grow(buffer, bottom, top);
+            }
+            (*buffer).write(bottom, value);
+        }
+
+        // The value has to be visible before the new bottom is, otherwise a thief could steal an empty slot
+        // A Release fence followed by a store works like a Release store
+        atomic::fence(Ordering::Release);
+        self.bottom.store(bottom + 1, Ordering::Relaxed);
+    }
+
+    // Owner only: pop from the bottom (LIFO)
+    unsafe fn pop(&self) -> Option<T> {
+        // Claim the bottom item first by moving bottom down...
+        let bottom = self.bottom.load(Ordering::Relaxed) - 1;
+        let buffer = self.buffer.load(Ordering::Relaxed);
+        self.bottom.store(bottom, Ordering::Relaxed);
+
+        // ...then look at top
+        // The SeqCst fence makes sure a thief can't read the old bottom while we read the old top - one of us sees the other's change
+        atomic::fence(Ordering::SeqCst);
+        let top = self.top.load(Ordering::Relaxed);
+
+        if top > bottom {
+            // It was already empty - put bottom back
+            self.bottom.store(bottom + 1, Ordering::Relaxed);
+            return None;
+        }
+
+        let value = unsafe { (*buffer).read(bottom) };
+
+        if top == bottom {
+            // This was the last item, so a thief might be going for it at the same time
+            // Whoever moves top forward first gets it
+            let won = self
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            // Either way the deque is now empty, with bottom == top
+            self.bottom.store(bottom + 1, Ordering::Relaxed);
+            if !won {
+                // A thief got it - our copy is thrown away without being dropped (it's MaybeUninit)
+                return None;
+            }
+        }
+
+        // More than one item left, so thieves (who only take from top) can't reach this one
+        Some(unsafe { value.assume_init() })
+    }
+
+    // Any thread: take from the top (FIFO)
+    fn steal(&self) -> Option<T> {
+        loop {
+            let top = self.top.load(Ordering::Acquire);
+            // Pairs with the fence in pop() (see above)
+            atomic::fence(Ordering::SeqCst);
+            let bottom = self.bottom.load(Ordering::Acquire);
+
+            if top >= bottom {
+                return None;
+            }
+
+            // Read the value before the CAS - once top moves, the owner is free to overwrite this slot
+            let buffer = self.buffer.load(Ordering::Acquire);
+            let value = unsafe { (*buffer).read(top) };
+
+            // Same pattern as LockFreeStack::pop(): "if top is still what I saw, move it forward"
+            match self
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+            {
+                // We own the value now
+                Ok(_) => return Some(unsafe { value.assume_init() }),
+                // Another thief (or the owner taking the last item) got there first
+                // Our copy is thrown away without being dropped, and we retry
+                Err(_) => {
+                    self.retry_count.fetch_add(1, Ordering::Relaxed);
+                    continue;
                 }
             }
-            // For this entire loop, we could use match statements instead of if let
-            // but this might be more verbose
-            // In the outer loop, if we encountered None we would go to the stealing logic None => {stealing}
-            // In the inner loop, if we encountered None, we would just increment the failed steals
-
-            // Create a thread-specific struct of stats
-            Stats {
-                local_tasks_completed,
-                stolen_tasks_completed,
-                steal_attempts, 
-                failed_steals,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.top.load(Ordering::Acquire) >= self.bottom.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for ChaseLevDeque<T> {
+    // Drop has &mut self, so no other thread can be using the deque anymore
+    fn drop(&mut self) {
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+        let buffer = *self.buffer.get_mut();
+        unsafe {
+            // Drop the items that are still in the deque
+            for index in top..bottom {
+                drop((*buffer).read(index).assume_init());
+            }
+            // Free the buffers - Box::from_raw turns the raw pointer back into a Box, which frees it when dropped
+            drop(Box::from_raw(buffer));
+            for old in self.retired.get_mut().unwrap().drain(..) {
+                drop(Box::from_raw(old));
             }
+        }
+    }
+}
+
+// push() and pop() are only safe when called from a single thread, so we don't let just anybody call them
+// new_deque() hands out two kinds of handles to the same deque:
+// - WorkerQueue: the owner's end (push_local / pop_local) - there is exactly one, and it can't be shared between threads
+// - Stealer: the thieves' end (steal) - it can be cloned and shared freely
+struct WorkerQueue<T> {
+    deque: Arc<ChaseLevDeque<T>>,
+    // PhantomData<Cell<()>> makes WorkerQueue !Sync (Cell isn't Sync)
+    // So a &WorkerQueue can never reach a second thread, which is exactly the "only one owner thread" rule
+    // It is still Send, so the owner end can be moved into the worker thread once
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+struct Stealer<T> {
+    deque: Arc<ChaseLevDeque<T>>,
+}
+
+fn new_deque<T>() -> (WorkerQueue<T>, Stealer<T>) {
+    let deque = Arc::new(ChaseLevDeque::new());
+    (
+        WorkerQueue { deque: Arc::clone(&deque), _not_sync: PhantomData },
+        Stealer { deque },
+    )
+}
+
+impl<T> WorkerQueue<T> {
+    fn push_local(&self, value: T) {
+        // SAFETY: WorkerQueue is !Sync and not Clone, so this is the only thread that can push or pop
+        unsafe { self.deque.push(value) }
+    }
+
+    fn pop_local(&self) -> Option<T> {
+        // SAFETY: same as push_local()
+        unsafe { self.deque.pop() }
+    }
+}
+
+impl<T> Stealer<T> {
+    fn steal(&self) -> Option<T> {
+        self.deque.steal()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.deque.is_empty()
+    }
+
+    fn retry_count(&self) -> usize {
+        self.deque.retry_count.load(Ordering::Relaxed)
+    }
+}
+
+// #[derive(Clone)] would require T: Clone, but cloning a Stealer only clones the Arc
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self { deque: Arc::clone(&self.deque) }
+    }
+}
+
+// Everything the workers share
+// We don't wrap the Vec in a Mutex because we never modify the Vec itself, only the queues inside it, which have their own locks
+// Coarse-grained: Arc<Mutex<Vec<WorkerQueue>>> → ONE lock, high contention
+// Fine-grained: Arc<Vec<WorkerQueue { tasks: Mutex<...> }>> → Multiple locks, low contention
+// Lock-free: Arc<Vec<Stealer>> → no locks at all on the per-worker queues
+struct Shared {
+    // The thieves' end of every worker's deque
+    // Each worker keeps the owner's end (WorkerQueue) to itself
+    stealers: Vec<Stealer<Job>>,
+    // Jobs submitted from outside the pool (from main, for example) go here
+    // Only a worker should push to its own queue, since the back of the queue is that worker's "hot" end
+    injector: Mutex<VecDeque<Job>>,
+    // Jobs that have been submitted (or spawned) but haven't finished running yet
+    // Workers use this to tell "every queue is empty right now" apart from "there is no work left at all":
+    // a job that is currently running may still spawn more subtasks
+    pending: AtomicUsize,
+    // Set by shutdown() - workers exit once pending reaches 0
+    // This replaces the Task::Shutdown poison pill, which only worked if exactly one pill reached each worker
+    shutdown: AtomicBool,
+}
+
+// What a running job gets to see of the worker running it
+struct WorkerContext<'a> {
+    worker_id: usize,
+    // The owner's end of this worker's deque
+    local: &'a WorkerQueue<Job>,
+    shared: &'a Shared,
+    // Subtasks spawned by the current job, added to the worker's Stats after the job returns
+    // Cell lets us change it through a & reference, since the job only gets &WorkerContext
+    spawned: Cell<usize>,
+}
+
+impl WorkerContext<'_> {
+    // Pushes a subtask onto the back of the CURRENT worker's queue
+    // It is the next thing this worker will run (LIFO), unless an idle worker steals it from the front first
+    fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce(&WorkerContext) + Send + 'static,
+    {
+        // Count it before it is visible to any other worker, so pending can't briefly drop to 0
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        self.local.push_local(Box::new(f));
+        self.spawned.set(self.spawned.get() + 1);
+    }
+
+    fn worker_id(&self) -> usize {
+        self.worker_id
+    }
+}
+
+struct WorkStealingPool {
+    shared: Arc<Shared>,
+    // Each worker returns its own Stats when it exits
+    handles: Vec<JoinHandle<Stats>>,
+}
+
+impl WorkStealingPool {
+    fn new(num_workers: usize) -> Self {
+        let num_workers = num_workers.max(1);
+
+        // Creating the worker queues
+        // This gives us queues[0], queues[1], etc.
+        // We are putting them all in a vector to make it easier to work with compared to having separate variables
+        // Benefits of Vec: can iterate with loops, can index by worker_id, easy to share ALL queues with each worker thread
+        // .unzip() splits an iterator of pairs into two collections
+        let (locals, stealers): (Vec<WorkerQueue<Job>>, Vec<Stealer<Job>>) =
+            (0..num_workers).map(|_| new_deque()).unzip();
+
+        let shared = Arc::new(Shared {
+            stealers,
+            injector: Mutex::new(VecDeque::new()),
+            pending: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
         });
 
-        // Pushing the handles to the vector so that we can iterate over it and call .join() later
-        handles.push(handle);
+        // Spawn the worker threads
+        let handles = locals
+            .into_iter()
+            .enumerate()
+            .map(|(worker_id, local)| {
+                // Creating a new pointer to the same data
+                // This will be moved into each individual thread so it can be used after the loop iteration ends
+                let shared = Arc::clone(&shared);
+                // The owner's end of the deque is moved into the worker thread and never leaves it
+                thread::spawn(move || run_worker(worker_id, local, &shared))
+            })
+            .collect();
+
+        Self { shared, handles }
     }
 
-    println!("Waiting for all workers to finish...\n");
+    // Submits a job from outside the pool
+    // It goes into the injector, and whichever worker runs out of local work first picks it up
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce(&WorkerContext) + Send + 'static,
+    {
+        self.shared.pending.fetch_add(1, Ordering::SeqCst);
+        self.shared.injector.lock().unwrap().push_back(Box::new(f));
+    }
+
+    // Waits for every submitted job (and every subtask they spawn) to finish, then stops the workers
+    // Returns the Stats of all workers added together
+    // Drop does the same thing, this just also hands back the Stats
+    fn shutdown(mut self) -> Stats {
+        let (total, all_joined) = self.stop_workers();
+        // run_worker catches job panics, so a worker can only die from a bug in the pool itself
+        assert!(all_joined, "a worker thread panicked");
+        total
+    }
+
+    // Sets the shutdown flag and joins every worker, even if one of them panicked, so none is left detached
+    // Returns the merged Stats and whether every worker exited normally
+    // Drains the handles, so calling it again (Drop after shutdown()) finds nothing left to join
+    fn stop_workers(&mut self) -> (Stats, bool) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+
+        let mut total = Stats::default();
+        let mut all_joined = true;
+        for handle in self.handles.drain(..) {
+            match handle.join() {
+                Ok(stats) => total.merge(&stats),
+                Err(_) => all_joined = false,
+            }
+        }
+        (total, all_joined)
+    }
+}
+
+// Without this, a pool dropped without shutdown() (an early return, a ?) would leave every worker polling forever
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        let (_, all_joined) = self.stop_workers();
+        // A second panic while already unwinding would abort the process, so only report it otherwise
+        if !all_joined && !thread::panicking() {
+            panic!("a worker thread panicked");
+        }
+    }
+}
+
+// Where a worker found the job it is about to run
+enum Source {
+    Local,
+    Injected,
+    Stolen,
+}
+
+// The loop each worker thread runs
+// We need to have local work and stealing work in the same loop because we want stealing to happen repeatedly whenever the local queue is empty
+
+// Worker strategy:
+// - Local work: pop_back() = LIFO (Last In, First Out) - most recent tasks (cache-hot)
+// - Injected work: pop_front() from the shared injector (FIFO) - jobs from outside the pool
+// - Stealing: pop_front() = FIFO (First In, First Out) - oldest tasks (less likely cache-hot)
+// This prevents conflict: worker processes recent work, thieves take old work
+fn run_worker(worker_id: usize, local: WorkerQueue<Job>, shared: &Shared) -> Stats {
+    // Local Stats for this thread - no locking needed since only this thread touches it
+    let mut stats = Stats::default();
+
+    // This is from the rand crate
+    // It must be mutable since RNG changes internal state each time you use it
+    // Each thread gets its own independent RNG
+    let mut rng = rand::rng();
+    let num_workers = shared.stealers.len();
+
+    loop {
+        // === Phase 1: Try local work first ===
+        // === Phase 2: Local empty, try the injector ===
+        // We bind the injector pop to a variable so the lock is released before we run the job
+        let found = match local.pop_local() {
+            Some(job) => Some((job, Source::Local)),
+            None => {
+                let injected = shared.injector.lock().unwrap().pop_front();
+                injected.map(|job| (job, Source::Injected))
+            }
+        };
+
+        // === Phase 3: Still nothing, try stealing from other workers ===
+        let found = found.or_else(|| {
+            // Pick a random starting victim, then walk around the ring from there
+            // The modulo (%) wraps the index back to 0 after the last worker
+            let start = rng.random_range(0..num_workers);
+            for offset in 0..num_workers {
+                let victim_id = (start + offset) % num_workers;
+                // Since threads should not steal from themselves, we always need to skip them
+                if victim_id == worker_id {
+                    continue;
+                }
+                stats.steal_attempts += 1;
+                if let Some(job) = shared.stealers[victim_id].steal() {
+                    // Found work, stop trying other victims
+                    return Some((job, Source::Stolen));
+                }
+                stats.failed_steals += 1;
+            }
+            None
+        });
+
+        match found {
+            Some((job, source)) => {
+                let context = WorkerContext {
+                    worker_id,
+                    local: &local,
+                    shared,
+                    spawned: Cell::new(0),
+                };
+                // The pool runs arbitrary user jobs, so one of them panicking must not take the worker down
+                // If the panic unwound through here, pending would never get its decrement below,
+                // the other workers would wait for it forever, and shutdown() would never return
+                // catch_unwind() turns the panic into an Err (the message is still printed by the panic hook)
+                // AssertUnwindSafe: the job only gets a &WorkerContext, and the deque is still consistent after a panic
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| job(&context)));
+                // Subtasks spawned before a panic are still queued (and counted in pending), so they count here either way
+                stats.subtasks_spawned += context.spawned.get();
+
+                match (outcome, source) {
+                    (Err(_), _) => stats.tasks_panicked += 1,
+                    (Ok(()), Source::Local) => stats.local_tasks_completed += 1,
+                    (Ok(()), Source::Injected) => stats.injected_tasks_completed += 1,
+                    (Ok(()), Source::Stolen) => stats.stolen_tasks_completed += 1,
+                }
+                // Only decrement after the job has run, so any subtasks it spawned were already counted
+                // This happens whether the job returned or panicked
+                shared.pending.fetch_sub(1, Ordering::SeqCst);
+            }
+            None => {
+                // === Phase 4: No work found anywhere ===
+                // Exit only when shutdown was requested AND no job is queued or running anywhere
+                // Checking the queues alone isn't enough: another worker may be running a job that is about to spawn more work
+                if shared.shutdown.load(Ordering::SeqCst) && shared.pending.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
 
-    // Create an empty vector to store the Stats struct from each thread
-    let mut all_stats: Vec<Stats> = Vec::new();
-
-    for handle in handles {
-        // First, calling .join() to allow all threads to finish before continuing
-        // We are storing this in a variable since the threads will produce a struct as output
-        let stats = handle.join().unwrap();
-        // .unwrap() panics if the thread panicked - acceptable for this example
-        // In production, you'd handle the Result properly
-        all_stats.push(stats);
-    }   
-
-
-    println!("=== Individual Worker Statistics ===");
-    // We are using a for loop with a tuple allowing us to put 2 items in the loop
-    // We are using .enumerate(), which gives us both the index and item in the vector (Stats struct)
-    // worker_id = the index (0, 1, 2, 3)
-    // stats = reference to the Stats struct at that index
-    for (worker_id, stats) in all_stats.iter().enumerate() {
-        // We can destructure what we are iterating over using tuples
-        // You can also do this with for, match, if let, let, function parameters, etc.
-        println!("Worker {}:", worker_id);
-        println!("  Local tasks completed: {}", stats.local_tasks_completed);
-        println!("  Stolen tasks completed: {}", stats.stolen_tasks_completed);
-        println!("  Steal attempts: {}", stats.steal_attempts);
-        println!("  Failed steals: {}", stats.failed_steals);
-        println!();
+                // Sleep briefly to avoid busy-waiting (hammering CPU)
+                // Still responsive enough to check for new work frequently
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
     }
+
+    stats
+}
+
+// ===== Benchmark: MutexWorkerQueue vs Chase-Lev =====
+// One owner pushes items and pops half of them back, while the thieves keep stealing from the other end
+// At the end the owner pops whatever is left
+// This is the access pattern a work-stealing pool produces
+// Build with optimizations for meaningful numbers (cargo run --release)
+
+fn bench_mutex(items: usize, thieves: usize) -> Duration {
+    let queue = MutexWorkerQueue::new();
+    let done = AtomicBool::new(false);
+    let received = AtomicUsize::new(0);
+    let start = Instant::now();
+
+    thread::scope(|s| {
+        for _ in 0..thieves {
+            s.spawn(|| {
+                while !done.load(Ordering::Acquire) || !queue.tasks.lock().unwrap().is_empty() {
+                    if queue.steal().is_some() {
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+
+        for i in 0..items {
+            queue.push_local(i);
+            if i % 2 == 0 && queue.pop_local().is_some() {
+                received.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // Whatever the thieves didn't get, the owner finishes off itself
+        while queue.pop_local().is_some() {
+            received.fetch_add(1, Ordering::Relaxed);
+        }
+        done.store(true, Ordering::Release);
+    });
+
+    // Every item must come out exactly once
+    assert_eq!(received.load(Ordering::Relaxed), items);
+    start.elapsed()
+}
+
+fn bench_chase_lev(items: usize, thieves: usize) -> (Duration, usize) {
+    let (local, stealer) = new_deque();
+    let done = AtomicBool::new(false);
+    let received = AtomicUsize::new(0);
+    let start = Instant::now();
+
+    thread::scope(|s| {
+        for _ in 0..thieves {
+            let stealer = stealer.clone();
+            let (done, received) = (&done, &received);
+            s.spawn(move || {
+                while !done.load(Ordering::Acquire) || !stealer.is_empty() {
+                    if stealer.steal().is_some() {
+                        received.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+
+        for i in 0..items {
+            local.push_local(i);
+            if i % 2 == 0 && local.pop_local().is_some() {
+                received.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // Whatever the thieves didn't get, the owner finishes off itself
+        while local.pop_local().is_some() {
+            received.fetch_add(1, Ordering::Relaxed);
+        }
+        done.store(true, Ordering::Release);
+    });
+
+    assert_eq!(received.load(Ordering::Relaxed), items);
+    (start.elapsed(), stealer.retry_count())
+}
+
+fn main() {
+
+    println!("=== Benchmark: Mutex<VecDeque> vs Chase-Lev deque ===");
+    let items = 200_000;
+    for thieves in [0, 1, 3] {
+        let mutex_time = bench_mutex(items, thieves);
+        let (chase_lev_time, retries) = bench_chase_lev(items, thieves);
+        println!(
+            "{} thieves: mutex {:?}, chase-lev {:?} ({} steal retries)",
+            thieves, mutex_time, chase_lev_time, retries
+        );
+    }
+    println!();
+
+    let pool = WorkStealingPool::new(4);
+
+    // One job that spawns 20 slow subtasks
+    // They all land on the back of whichever worker ran this job, so that worker is overloaded
+    pool.execute(|ctx| {
+        println!("Worker {}: spawning 20 slow subtasks", ctx.worker_id());
+        for id in 0..20 {
+            ctx.spawn(move |ctx| {
+                println!("Worker {}: slow subtask {}", ctx.worker_id(), id);
+                thread::sleep(Duration::from_millis(200));
+            });
+        }
+    });
+
+    // 15 fast jobs from outside the pool
+    for id in 0..15 {
+        pool.execute(move |ctx| {
+            println!("Worker {}: fast job {}", ctx.worker_id(), id);
+            thread::sleep(Duration::from_millis(50));
+        });
+    }
+
+    // Subtasks can spawn their own subtasks (recursive divide and conquer)
+    pool.execute(|ctx| {
+        for half in 0..2 {
+            ctx.spawn(move |ctx| {
+                for quarter in 0..2 {
+                    ctx.spawn(move |ctx| println!("Worker {}: leaf {}.{}", ctx.worker_id(), half, quarter));
+                }
+            });
+        }
+    });
+
+    // A job that panics after spawning a subtask
+    // The worker survives, the subtask still runs, and shutdown() still returns
+    pool.execute(|ctx| {
+        ctx.spawn(|ctx| println!("Worker {}: subtask of a panicking job", ctx.worker_id()));
+        panic!("job failed on purpose");
+    });
+
+    println!("Waiting for all workers to finish...\n");
+    let stats = pool.shutdown();
+
+    println!("\n=== Aggregated Worker Statistics ===");
+    println!("  Local tasks completed: {}", stats.local_tasks_completed);
+    println!("  Injected tasks completed: {}", stats.injected_tasks_completed);
+    println!("  Stolen tasks completed: {}", stats.stolen_tasks_completed);
+    println!("  Tasks panicked: {}", stats.tasks_panicked);
+    println!("  Subtasks spawned: {}", stats.subtasks_spawned);
+    println!("  Steal attempts: {}", stats.steal_attempts);
+    println!("  Failed steals: {}", stats.failed_steals);
 }
 
 // Expected behavior:
-// - Worker 0 starts with 20 tasks (200ms each) = 4000ms of work
-// - Workers 1,2,3 start with 5 tasks each (50ms each) = 250ms of work
-// - Without stealing: Worker 0 takes 4s alone, others idle after 250ms
-// - With stealing: Workers 1,2,3 steal from Worker 0 → balanced workload → faster completion
\ No newline at end of file
+// - One worker gets 20 subtasks (200ms each) = 4000ms of work on its own queue
+// - The 15 fast jobs (50ms each) = 750ms of work spread over everyone through the injector
+// - Without stealing: that one worker takes 4s alone, others idle after ~250ms
+// - With stealing: the other workers steal the slow subtasks → balanced workload → faster completion
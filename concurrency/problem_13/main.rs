@@ -52,6 +52,8 @@
 
 use std::thread::{self, JoinHandle};
 use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 // This is a function with generic type parameters
 // T: The type of elements in your input data
@@ -203,6 +205,119 @@ where // The trait bounds
 // 9. Start the final reduction phase, which entails collecting all partial results from the channel into an intermediate vector
 // 10. The final result is then acquired through doing one last reduction on the intermediate vector combining all partial results
 
+// This first version has some problems:
+// - .to_vec() clones every element just so the threads can own their chunk
+// - .reduce().unwrap() panics when the input is empty (there is nothing to reduce)
+// - Partial results are combined in the order they arrive on the channel, not in the order of the chunks
+//   That is fine for + and *, but not for string concatenation: "MAP REDUCE HELLO PARALLEL" is a valid answer
+// - Every worker gets exactly one chunk, so if one chunk is slower than the others, the rest of the workers sit idle
+
+// -----
+
+// Second version: parallel_map_reduce_ordered
+
+// How the data gets split into chunks
+// Workers don't get assigned chunks up front - they keep grabbing the next chunk until the data runs out
+// So a worker that finishes early just takes more chunks (load balancing)
+#[derive(Debug, Clone, Copy)]
+enum ChunkStrategy {
+    // Every chunk has this many elements
+    // Small = better balancing but more overhead per chunk
+    Fixed(usize),
+    // One chunk per worker, same split as parallel_map_reduce
+    PerWorker,
+    // Start with big chunks and shrink them as the data runs out: remaining / (2 * workers), but never below min_chunk
+    // Big chunks early keep the overhead low, small chunks at the end let idle workers help finish the last bits
+    Guided { min_chunk: usize },
+}
+
+impl ChunkStrategy {
+    // How big the next chunk should be, given how many elements are left
+    fn next_chunk_size(&self, len: usize, remaining: usize, num_workers: usize) -> usize {
+        let size = match *self {
+            ChunkStrategy::Fixed(size) => size,
+            ChunkStrategy::PerWorker => len.div_ceil(num_workers),
+            ChunkStrategy::Guided { min_chunk } => (remaining / (2 * num_workers)).max(min_chunk),
+        };
+        size.clamp(1, remaining)
+    }
+}
+
+// Differences from parallel_map_reduce:
+// - data is borrowed (&[T]) and the workers run in thread::scope, so nothing is cloned
+//   T only needs to be Sync (workers read it through shared references)
+// - identity() gives the starting value for every chunk and the answer for empty input
+//   It must really be an identity for reduce_fn: reduce_fn(identity(), x) == x (0 for +, 1 for *, "" for concatenation)
+// - Partial results are combined in chunk order, so reduce_fn only has to be associative, not commutative
+fn parallel_map_reduce_ordered<T, R, I, M, F>(
+    data: &[T],
+    num_workers: usize,
+    strategy: ChunkStrategy,
+    identity: I,
+    map_fn: M,
+    reduce_fn: F,
+) -> R
+where
+    T: Sync,
+    R: Send,
+    I: Fn() -> R + Sync,
+    M: Fn(&T) -> R + Sync,
+    F: Fn(R, R) -> R + Sync,
+{
+    let num_workers = num_workers.max(1);
+
+    // Start of the next chunk nobody has taken yet
+    let next_start = AtomicUsize::new(0);
+
+    let mut partials: Vec<(usize, R)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..num_workers)
+            .map(|_| {
+                // Closures passed to spawn need their captures by reference, which thread::scope allows
+                let next_start = &next_start;
+                let identity = &identity;
+                let map_fn = &map_fn;
+                let reduce_fn = &reduce_fn;
+                s.spawn(move || {
+                    // (chunk start, partial result) for every chunk this worker did
+                    let mut mine = Vec::new();
+                    loop {
+                        // Claim [start, start + size) with a CAS
+                        // With Guided the size depends on how much is left, so we can't just fetch_add
+                        let mut start = next_start.load(Ordering::Relaxed);
+                        let size = loop {
+                            if start >= data.len() {
+                                return mine;
+                            }
+                            let size = strategy.next_chunk_size(data.len(), data.len() - start, num_workers);
+                            match next_start.compare_exchange_weak(start, start + size, Ordering::Relaxed, Ordering::Relaxed) {
+                                Ok(_) => break size,
+                                Err(current) => start = current,
+                            }
+                        };
+
+                        // Map and reduce the chunk locally, starting from the identity
+                        let partial = data[start..start + size]
+                            .iter()
+                            .fold(identity(), |acc, element| reduce_fn(acc, map_fn(element)));
+                        mine.push((start, partial));
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    // Put the partial results back in data order before combining them
+    partials.sort_by_key(|(start, _)| *start);
+    partials
+        .into_iter()
+        .fold(identity(), |acc, (_, partial)| reduce_fn(acc, partial))
+}
+
 fn main() {
     // Test 1: Sum of squares
     let data = vec![1, 2, 3, 4, 5, 6, 7, 8];
@@ -258,6 +373,62 @@ fn main() {
     
     println!("Sum of doubled 1-1000: {}", sum);
     // Expected: 2 * (1+2+...+1000) = 2 * 500500 = 1001000
+
+
+    // parallel_map_reduce_ordered -----
+    println!("\n=== Ordered map-reduce ===");
+
+    // Concatenation is not commutative, so the order of the chunks matters
+    // With chunks of 1 word, 4 workers race for them, but the result always comes out in order
+    // The reducer skips the separator next to the identity "", so it really is an identity
+    let words: Vec<String> = "the quick brown fox jumps over the lazy dog"
+        .split(' ')
+        .map(String::from)
+        .collect();
+    let join = |a: String, b: String| {
+        if a.is_empty() {
+            b
+        } else if b.is_empty() {
+            a
+        } else {
+            format!("{} {}", a, b)
+        }
+    };
+    for _ in 0..3 {
+        let sentence = parallel_map_reduce_ordered(&words, 4, ChunkStrategy::Fixed(1), String::new, |s| s.to_uppercase(), join);
+        println!("Sentence: {}", sentence);
+    }
+    // words is still ours - nothing was cloned into the workers
+    println!("Input still available: {} words", words.len());
+
+    // Empty input returns the identity instead of panicking
+    let empty: Vec<i64> = Vec::new();
+    let total = parallel_map_reduce_ordered(&empty, 4, ChunkStrategy::PerWorker, || 0, |x| x * x, |a, b| a + b);
+    println!("Sum of squares of nothing: {}", total);
+
+    // Uneven work: element i costs i units, so the last chunks are much slower than the first ones
+    // With PerWorker, the worker that gets the last quarter does almost half of the work alone
+    // Fixed and Guided let the other workers pick up the slack (on a single core all three take about as long)
+    let costs: Vec<u64> = (0..2_000).collect();
+    let slow_square = |x: &u64| {
+        let mut acc = 0u64;
+        for i in 0..*x * 20 {
+            acc = acc.wrapping_add(i ^ x);
+        }
+        // black_box stops the compiler from noticing acc is unused and deleting the busy loop
+        std::hint::black_box(acc);
+        x * x
+    };
+    let strategies = [
+        ("per-worker", ChunkStrategy::PerWorker),
+        ("fixed(64)", ChunkStrategy::Fixed(64)),
+        ("guided(8)", ChunkStrategy::Guided { min_chunk: 8 }),
+    ];
+    for (name, strategy) in strategies {
+        let started = Instant::now();
+        let sum = parallel_map_reduce_ordered(&costs, 4, strategy, || 0u64, slow_square, |a, b| a + b);
+        println!("{:<11} sum = {}  ({:?})", name, sum, started.elapsed());
+    }
 }
 
 // The ultimate goal of map-reduce is that each thread does it's own mapping and initial reduction
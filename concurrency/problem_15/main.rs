@@ -40,6 +40,7 @@
 // 2. Pop without locks (use CAS to remove from top)
 // 3. Handle concurrent pushes and pop directly
 // 4. Track CAS retries to see contention
+// 5. Build a FIFO queue the same way (Michael-Scott queue, further down)
 
 // Traditional stack with locks:
     // Stack: [3] -> [2] -> [1] -> null
@@ -83,10 +84,28 @@
 // Thread 1's CAS succeeds but stack structure changed
 // Solution: Use AtomicPtr carefully
 
-use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
-use std::sync::Arc;
+// There is a second, nastier problem hiding in pop(): use-after-free
+// Thread 1 reads top = A and is about to read A.next
+// Thread 2 pops A and frees the node
+// Thread 1 now reads A.next from memory that no longer belongs to us
+// We can't free a popped node the moment we unlink it, because another thread might still be looking at it
+// The fix is a reclamation scheme - we use hazard pointers:
+// - Before a thread dereferences a node, it publishes the node's address in a "hazard" slot ("I am looking at this, don't free it")
+// - A popped node is not freed, it is "retired" onto a list
+// - Every so often we scan the retired list and only free the nodes that nobody has published as a hazard
+// Because a protected node can't be freed, its address can't be reused either, so this also closes the ABA hole above
+
+use std::cell::{Cell, UnsafeCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::hint;
+use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
 use std::thread;
-use std::ptr;
+use std::ptr::{self, NonNull};
+use std::time::Instant;
 use crate::thread::JoinHandle;
 
 // This first struct is a linked list
@@ -109,7 +128,9 @@ use crate::thread::JoinHandle;
 
 // This will be one element in the linked list
 struct Node<T> {
-    value: T, // The data we are storing
+    value: ManuallyDrop<T>, // The data we are storing
+    // ManuallyDrop because pop() moves the value out long before the node itself is freed
+    // When the retired node is finally freed, we must not drop the value a second time
     next: *mut Node<T>, // Pointer to the next node (null means "no next node" or end of the list)
     // It is an address in memory where a Node lives and we can use that address to access and modify the node
     // This is a regular raw pointer, NOT atomic
@@ -170,6 +191,245 @@ struct LockFreeStack<T> {
     // The top node will always be wrapped in an atomic pointer, the rest won't 
 
     retry_count: AtomicUsize,
+
+    // Elimination array (see "Elimination backoff" below)
+    // Empty when the stack was made with new(), which keeps the plain CAS-and-retry behaviour
+    elimination: Vec<AtomicPtr<Node<T>>>,
+    eliminations: AtomicUsize, // push/pop pairs that traded a value through the array
+    backoff_spins: AtomicUsize, // spin_loop iterations spent backing off after a failed CAS
+
+    // Hazard pointers and retired nodes for pop() (see HazardDomain below)
+    hazards: HazardDomain<Node<T>>,
+
+    // AtomicPtr is Send + Sync no matter what it points to, so without this the compiler would
+    // happily let a LockFreeStack<Rc<i32>> cross threads
+    // A raw pointer is neither Send nor Sync, which switches the automatic impls off
+    // We then write the impls ourselves below, with the bound we actually need
+    _marker: PhantomData<*mut Node<T>>,
+}
+
+// Sending the stack to another thread sends every T inside it, so T must be Send
+unsafe impl<T: Send> Send for LockFreeStack<T> {}
+
+// Sharing &LockFreeStack lets other threads push and pop, which moves T values between threads
+// The stack never hands out &T, so T does not need to be Sync - T: Send is enough (same as Mutex<T>)
+unsafe impl<T: Send> Sync for LockFreeStack<T> {}
+
+// A hazard record is a slot a thread borrows for the duration of one operation (stack pop, queue push or pop)
+// - hazards: the nodes this thread is about to dereference (null when it isn't looking at anything)
+//   The stack only ever needs one, the queue further down needs two (head and head.next)
+// - active: whether some thread currently owns this record
+// - retired: nodes popped by the owner that are waiting to be freed
+// - next: the next record in the list (written once before the record is published, never changed after)
+
+// Only the thread that flipped active from false to true touches retired, so it can live in an UnsafeCell
+// The Release store when giving the record back and the Acquire CAS when taking it make the Vec visible to the next owner
+
+// N is the node type, so the same code works for the stack's Node<T> and the queue's QueueNode<T>
+struct HazardRecord<N> {
+    hazards: [AtomicPtr<N>; HAZARDS_PER_RECORD],
+    active: AtomicBool,
+    retired: UnsafeCell<Vec<*mut N>>,
+    next: *mut HazardRecord<N>,
+}
+
+const HAZARDS_PER_RECORD: usize = 2;
+
+// Once a record has this many retired nodes, we scan the hazards and free what we can
+// Lower = less memory held back, higher = fewer scans
+const RETIRE_THRESHOLD: usize = 16;
+
+impl<N> HazardRecord<N> {
+    // Publish "I am looking at node"
+    // SeqCst here (and in the CAS that unlinks a node and in scan) keeps all of these in one global order,
+    // which is what makes "store the hazard, then check the node is still reachable" valid
+    fn protect(&self, slot: usize, node: *mut N) {
+        self.hazards[slot].store(node, Ordering::SeqCst);
+    }
+
+    // Stop protecting anything
+    fn clear(&self) {
+        for hazard in &self.hazards {
+            hazard.store(ptr::null_mut(), Ordering::Release);
+        }
+    }
+
+    // Give the record back so another thread can use it
+    fn release(&self) {
+        self.clear();
+        self.active.store(false, Ordering::Release);
+    }
+}
+
+// All the hazard records for one data structure, plus the reclamation logic
+struct HazardDomain<N> {
+    // Linked list of hazard records, one per thread currently (or previously) inside push/pop
+    // Records are only ever added, never removed, until the domain itself is dropped
+    records: AtomicPtr<HazardRecord<N>>,
+
+    // How many retired nodes have actually been freed so far
+    reclaimed_count: AtomicUsize,
+}
+
+impl<N> HazardDomain<N> {
+    fn new() -> Self {
+        Self {
+            records: AtomicPtr::new(ptr::null_mut()),
+            reclaimed_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn reclaimed_count(&self) -> usize {
+        self.reclaimed_count.load(Ordering::Relaxed)
+    }
+
+    // Find a free hazard record, or add a new one if every record is in use
+    // The list only grows to the maximum number of threads that were ever inside at the same time
+    fn acquire(&self) -> &HazardRecord<N> {
+        // First try to reuse a record some other thread has given back
+        let mut current = self.records.load(Ordering::Acquire);
+        while !current.is_null() {
+            // Records are never freed while the domain is alive, so this reference is always valid
+            let record = unsafe { &*current };
+            if record
+                .active
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return record;
+            }
+            current = record.next;
+        }
+
+        // Every record is busy - allocate a new one that is already marked active
+        let record = Box::into_raw(Box::new(HazardRecord {
+            hazards: [AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut())],
+            active: AtomicBool::new(true),
+            retired: UnsafeCell::new(Vec::new()),
+            next: ptr::null_mut(),
+        }));
+
+        // And push it onto the front of the list, exactly like push() does for nodes
+        loop {
+            let head = self.records.load(Ordering::Acquire);
+            // Nobody can see the record yet, so writing next is fine
+            unsafe {
+                (*record).next = head;
+            }
+            if self
+                .records
+                .compare_exchange(head, record, Ordering::Release, Ordering::Acquire)
+                .is_ok()
+            {
+                return unsafe { &*record };
+            }
+        }
+    }
+
+    // Put an unlinked node on the record's retired list, and scan once the list is long enough
+    fn retire(&self, record: &HazardRecord<N>, node: *mut N) {
+        // We own the record (active == true), so nobody else is touching retired
+        let retired = unsafe { &mut *record.retired.get() };
+        retired.push(node);
+        if retired.len() >= RETIRE_THRESHOLD {
+            self.scan(retired);
+        }
+    }
+
+    // Free every retired node that no thread currently has as a hazard
+    fn scan(&self, retired: &mut Vec<*mut N>) {
+        // Collect the hazards of every record (active or not - an inactive one is just null)
+        let mut protected = Vec::new();
+        let mut current = self.records.load(Ordering::Acquire);
+        while !current.is_null() {
+            let record = unsafe { &*current };
+            for hazard in &record.hazards {
+                let hazard = hazard.load(Ordering::SeqCst);
+                if !hazard.is_null() {
+                    protected.push(hazard);
+                }
+            }
+            current = record.next;
+        }
+
+        // Keep the protected ones for the next scan, free the rest
+        // The node's value was already moved out by pop(), and the node types make sure it isn't dropped again
+        // (ManuallyDrop in Node, MaybeUninit in QueueNode)
+        let before = retired.len();
+        retired.retain(|&node| {
+            if protected.contains(&node) {
+                true
+            } else {
+                unsafe {
+                    drop(Box::from_raw(node));
+                }
+                false
+            }
+        });
+        self.reclaimed_count
+            .fetch_add(before - retired.len(), Ordering::Relaxed);
+    }
+}
+
+// When the domain is dropped, the structure that owns it is being dropped too, so no thread can hold a hazard
+// Every retired node can be freed, along with the records themselves
+impl<N> Drop for HazardDomain<N> {
+    fn drop(&mut self) {
+        let mut current = *self.records.get_mut();
+        while !current.is_null() {
+            // Take ownership of the record back from the raw pointer
+            let record = unsafe { Box::from_raw(current) };
+            for node in record.retired.into_inner() {
+                unsafe {
+                    drop(Box::from_raw(node));
+                }
+            }
+            current = record.next;
+        }
+    }
+}
+
+// Contention counters for LockFreeStack
+// cas_retries: failed CAS on top (same as retry_count())
+// eliminations: push/pop pairs that met in the elimination array instead of retrying
+// backoff_spins: total spin_loop iterations spent in exponential backoff
+#[derive(Debug, Clone, Copy)]
+struct RetryStats {
+    cas_retries: usize,
+    eliminations: usize,
+    backoff_spins: usize,
+}
+
+// How long a push waits in the elimination array for a pop
+const ELIMINATION_SPINS: usize = 128;
+
+// Upper limit for one round of exponential backoff
+const MAX_BACKOFF_SPINS: usize = 1024;
+
+// Marker stored in an elimination slot once a pop has taken the offered node
+// A dangling pointer is never a real allocation, so it can't be mistaken for a node
+fn taken<T>() -> *mut Node<T> {
+    NonNull::dangling().as_ptr()
+}
+
+// Cheap per-thread random number for picking an elimination slot
+// Threads that pick different slots don't contend with each other (xorshift, seeded from the thread id)
+fn random_index(len: usize) -> usize {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            let mut hasher = DefaultHasher::new();
+            thread::current().id().hash(&mut hasher);
+            hasher.finish() | 1
+        });
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x % len as u64) as usize
+    })
 }
 
 // When a struct is generic, its impl must also be generic over the same type parameters
@@ -191,9 +451,22 @@ impl<T> LockFreeStack<T> {
             top: AtomicPtr::new(ptr::null_mut()), // ptr::null_mut() creates a null mutable pointer
             // We are initializing an AtomicPtr with a null pointer, which is a common way to represent "no value yet"
             retry_count: AtomicUsize::new(0),
+            elimination: Vec::new(),
+            eliminations: AtomicUsize::new(0),
+            backoff_spins: AtomicUsize::new(0),
+            hazards: HazardDomain::new(),
+            _marker: PhantomData,
         }
     }
 
+    // Create an empty stack with an elimination array of `slots` slots
+    // A good size is around half the number of threads that hit the stack at the same time
+    fn with_elimination(slots: usize) -> Self {
+        let mut stack = Self::new();
+        stack.elimination = (0..slots.max(1)).map(|_| AtomicPtr::new(ptr::null_mut())).collect();
+        stack
+    }
+
     // Check if the stack is empty
     // If it points to an existing node in memory, stack is not empty
     fn is_empty(&self) -> bool {
@@ -221,7 +494,7 @@ impl<T> LockFreeStack<T> {
         // It gives us ownership of the heap memory but as a raw pointer
         // We need this to be raw pointer since all nodes will be raw pointers
         let new_node = Box::into_raw(Box::new(Node {
-            value,
+            value: ManuallyDrop::new(value),
             // We will update this in the loop before CAS
             next: ptr::null_mut(),
         }));
@@ -234,6 +507,7 @@ impl<T> LockFreeStack<T> {
         // CAS might fail if another thread modified top first
         // We keep trying until we succeed
         // Lock free - no blocking, just retry
+        let mut attempt = 0;
         loop {
 
             // Step 3: Read the current top pointer -----
@@ -277,7 +551,12 @@ impl<T> LockFreeStack<T> {
                 }
                 Err(_) => {
                     self.retry_count.fetch_add(1, Ordering::Relaxed);
-                    // Failed - someone else chaned top, retry
+                    // Failed - someone else chaned top
+                    // Before retrying, see if a pop is waiting in the elimination array to take our value directly
+                    if self.try_eliminate_push(new_node) {
+                        return;
+                    }
+                    self.backoff(&mut attempt);
                     continue;
                 }
             }
@@ -356,7 +635,13 @@ impl<T> LockFreeStack<T> {
     // This function is for taking data out of the stack
     fn pop(&self) -> Option<T> {
 
-        loop {
+        // Step 0: Borrow a hazard record for this pop -----
+
+        // We need somewhere to announce which node we are about to dereference
+        let record = self.hazards.acquire();
+        let mut attempt = 0;
+
+        let result = loop {
             // Step 1: Load the current top -----
 
             // As before, we are loading the current top using .load()
@@ -370,10 +655,21 @@ impl<T> LockFreeStack<T> {
             // Now, we need to check if the stack is empty
             // If the stack is empty, meaning .load() points to null (null (0x0)), we return nothing
             if current_top.is_null() {
-                return None;
+                break None;
+            }
+
+            // Step 3: Protect the node with a hazard pointer -----
+
+            // Publish "I am looking at current_top" before we touch it
+            // Then check that it is still the top - if it is, nobody could have retired it before our hazard became visible,
+            // and anyone who retires it from now on will see our hazard when scanning and leave it alone
+            record.protect(0, current_top);
+            if self.top.load(Ordering::SeqCst) != current_top {
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+                continue;
             }
 
-            // Step 3: Read the next pointer from the current top node
+            // Step 4: Read the next pointer from the current top node
 
             // We do this before CAS
             // We need to use unsafe since we are dealing with raw pointers
@@ -381,11 +677,12 @@ impl<T> LockFreeStack<T> {
             // We dereference the raw pointer to access the node's (current top) fields
             // next gets the raw pointer to the next node (or null if this was the last node)
             // We need this so we know what to point to after removing the top node
+            // This is only safe because of the hazard - without it the node could already be freed
             let next = unsafe {
                 (*current_top).next
             };
 
-            // Step 4: Try to update top to point to the next (removing current_top) -----
+            // Step 5: Try to update top to point to the next (removing current_top) -----
 
             // We are using .compare_exchange()
             // If the top is current_top (the value we expect), we make the swap
@@ -393,39 +690,47 @@ impl<T> LockFreeStack<T> {
             match self.top.compare_exchange(
                 current_top, // Expected: top should still be current_top
                 next, // New: make top point to next
-                Ordering::Release, // Success ordering
+                Ordering::SeqCst, // Success ordering
                 Ordering::Acquire, // Failure ordering
             ) {
                 Ok(_) => {
                     // Success - we removed current top from the stack
-                    // Now we need to extract the value and free the memory
-                    
-                    // Step 5: Convert raw pointer back to Box -----
-                    
-                    // This will take ownership and will drop (free) the node when it goes out of scope
-                    // This converts from *mut Node<T> to Box<Node<T>>
-                    // Takes ownership of the memory
-                    // Will automatically free the memory when the Box is dropped
-                    let node = unsafe {
-                        Box::from_raw(current_top)
+                    // Nobody else can pop it now, so we no longer need to protect it
+                    record.clear();
+
+                    // Step 6: Move the value out -----
+
+                    // We only copy the value out of the node, we do NOT free the node
+                    // Another thread may still be in Step 4 reading current_top.next
+                    let value = unsafe {
+                        ManuallyDrop::into_inner(ptr::read(ptr::addr_of!((*current_top).value)))
                     };
 
-                    // Step 6: Extract and return the value ------
-                    // The Box is dropped here, freeing the memory
-                    // node goes out of scope and is dropped
-                    // The box automatically frees the heap memory
-                    return Some(node.value);
+                    // Step 7: Retire the node -----
+
+                    // It will be freed later, once no hazard points at it
+                    self.hazards.retire(record, current_top);
+                    break Some(value);
                 }
                 Err(_) => {
                     self.retry_count.fetch_add(1, Ordering::Relaxed);
                     // Failed - another thread modified the top
-                    // Loop back and retry
+                    // We don't need the node protected while we look at the elimination array
+                    record.clear();
+                    if let Some(value) = self.try_eliminate_pop() {
+                        break Some(value);
+                    }
+                    // Back off, then loop back and retry
+                    self.backoff(&mut attempt);
                     continue;
                 }
             }
 
-        }
+        };
 
+        // Give the record back so another pop can use it
+        record.release();
+        result
     }
     // Step 1: Read the current top
     // We read the current top pointer, whiich returns *mut Node<T> (could be null or an address)
@@ -440,7 +745,11 @@ impl<T> LockFreeStack<T> {
 
         // current_top.is_null() = true
         // Return None
-    // Step 3: Read the next pointer
+    // Step 3: Protect the node
+    // Store current_top in our hazard slot, then re-read top
+    // If top changed in between, the node might already be retired, so we start over
+    // If it didn't, the node is guaranteed to stay allocated until we clear the hazard
+    // Step 4: Read the next pointer
     // We dereference the raw pointer to access the node
     // current_top = *mut Node<T>
     // (*current_top) = Node<T>
@@ -458,19 +767,21 @@ impl<T> LockFreeStack<T> {
         // top = [3] → [1] → null
         // ^
         // This is what we read as 'next'
-    // Step 4: Try to update top to point to next (removing current_top)
+    // Step 5: Try to update top to point to next (removing current_top)
     // We use .compare_exchange() to atomically update top
     // Atomically: "If top is STILL current_top, change it to next"
     // Success (Ok): current_top was removed from stack
     //   - We now own the removed node
-    //   - Must extract value and free memory using Box::from_raw
-    //   - Box automatically frees memory when dropped
+    //   - Move the value out and retire the node (Steps 6 and 7)
+    //   - We can't free it yet, other threads may hold a hazard on it
     // Failure (Err): Another thread changed top
     //   - Loop back and retry with new top value
 
     // Memory management cycle:
-    // push(): Box::new() → Box::into_raw() (Box → raw pointer, manual management)
-    // pop():  Box::from_raw() → Box dropped (raw pointer → Box, automatic free)
+    // push():  Box::new() → Box::into_raw() (Box → raw pointer, manual management)
+    // pop():   value moved out → node pushed onto our record's retired list
+    // scan():  Box::from_raw() → Box dropped, only for retired nodes no hazard points at
+    // drop():  whatever is still retired gets freed when the stack goes away
 
     // When we push(), the new top node has to point to the previous top node
         // Before:
@@ -493,9 +804,816 @@ impl<T> LockFreeStack<T> {
     fn retry_count(&self) -> usize {
         self.retry_count.load(Ordering::Relaxed)
 }
+
+    // retry_count plus what happened after each failed CAS
+    fn retry_stats(&self) -> RetryStats {
+        RetryStats {
+            cas_retries: self.retry_count.load(Ordering::Relaxed),
+            eliminations: self.eliminations.load(Ordering::Relaxed),
+            backoff_spins: self.backoff_spins.load(Ordering::Relaxed),
+        }
+    }
+
+    // Elimination backoff -----
+
+    // Under heavy contention most CAS attempts on top fail, and every thread just spins on the same pointer
+    // But a push and a pop that collide cancel each other out: the pop would just return the value the push adds
+    // So instead of both going through top, they can meet somewhere else and hand the value over directly
+    // That "somewhere else" is the elimination array - a handful of slots spread out so threads don't fight over one pointer
+
+    // Each slot is:
+    // - null: free
+    // - a node pointer: a push is offering this node, waiting for a pop
+    // - TAKEN: a pop took the offered node, the push hasn't noticed yet
+        // Push:  CAS null → node, spin a little, then either see TAKEN (done!) or CAS node → null (withdraw)
+        // Pop:   see a node, CAS node → TAKEN, the node and its value are now ours
+
+    // If nobody shows up, we fall back to exponential backoff: wait 1, 2, 4, ... spins before trying top again,
+    // so the threads that failed spread out in time instead of colliding again straight away
+
+    // Offer our node to a pop, returns true if a pop took it
+    fn try_eliminate_push(&self, node: *mut Node<T>) -> bool {
+        if self.elimination.is_empty() {
+            return false;
+        }
+        let slot = &self.elimination[random_index(self.elimination.len())];
+
+        // Someone else is using this slot, just back off
+        // Release so the pop that takes the node also sees the value we wrote into it
+        if slot
+            .compare_exchange(ptr::null_mut(), node, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+
+        // Wait a little for a pop to come along
+        for _ in 0..ELIMINATION_SPINS {
+            if slot.load(Ordering::Acquire) == taken() {
+                // Traded - free the slot for the next pair
+                // Only we can do this, no other push can offer into a slot that isn't null
+                slot.store(ptr::null_mut(), Ordering::Release);
+                return true;
+            }
+            hint::spin_loop();
+        }
+
+        // Nobody came - take the offer back
+        // If this CAS fails, a pop took the node just now, so we were eliminated after all
+        match slot.compare_exchange(node, ptr::null_mut(), Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => false,
+            Err(_) => {
+                slot.store(ptr::null_mut(), Ordering::Release);
+                true
+            }
+        }
+    }
+
+    // Look for a push waiting in the array and take its value
+    fn try_eliminate_pop(&self) -> Option<T> {
+        if self.elimination.is_empty() {
+            return None;
+        }
+        let slot = &self.elimination[random_index(self.elimination.len())];
+
+        let offered = slot.load(Ordering::Acquire);
+        if offered.is_null() || offered == taken() {
+            return None;
+        }
+        // Claim it - if this succeeds, the node was never on the stack and nobody else can reach it
+        // so we can free it right away, no hazard pointers needed
+        slot.compare_exchange(offered, taken(), Ordering::AcqRel, Ordering::Relaxed)
+            .ok()?;
+        self.eliminations.fetch_add(1, Ordering::Relaxed);
+        let node = unsafe { Box::from_raw(offered) };
+        Some(ManuallyDrop::into_inner(node.value))
+    }
+
+    // Spin for 1, 2, 4, ... up to MAX_BACKOFF_SPINS iterations, doubling on each failed attempt
+    // Only used together with the elimination array, new() keeps retrying straight away
+    fn backoff(&self, attempt: &mut u32) {
+        if self.elimination.is_empty() {
+            return;
+        }
+        let spins = (1usize << (*attempt).min(16)).min(MAX_BACKOFF_SPINS);
+        for _ in 0..spins {
+            hint::spin_loop();
+        }
+        self.backoff_spins.fetch_add(spins, Ordering::Relaxed);
+        *attempt += 1;
+    }
+
+    // Pop everything that is on the stack, one value at a time
+    // This goes through pop(), so other threads can keep pushing and popping while we drain
+    // Anything pushed while draining may or may not show up
+    fn drain(&self) -> Drain<'_, T> {
+        Drain { stack: self }
+    }
+
+    fn reclaimed_count(&self) -> usize {
+        self.hazards.reclaimed_count()
+    }
+}
+
+// When the stack is dropped we have &mut self, so no other thread can be inside push or pop
+// That means no hazard can be set, and we can free everything without any atomics dance:
+// - Nodes still on the stack: drop the value AND free the node (here)
+// - Retired nodes: only free the node, their value was already moved out by pop() (HazardDomain's Drop, which runs right after)
+impl<T> Drop for LockFreeStack<T> {
+    fn drop(&mut self) {
+        // Walk the list from the top, like the traversal at the top of the file
+        let mut node = *self.top.get_mut();
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            // The value is still in there, so we have to drop it ourselves (ManuallyDrop won't)
+            unsafe {
+                ManuallyDrop::drop(&mut boxed.value);
+            }
+            // boxed is dropped here, freeing the node
+        }
+    }
+}
+
+// Iterator returned by drain()
+// It borrows the stack, so the stack is still usable (and empty, unless someone pushed meanwhile) afterwards
+struct Drain<'a, T> {
+    stack: &'a LockFreeStack<T>,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+}
+
+// Iterator returned by into_iter()
+// It owns the stack, so once we run out of values (or stop early) the stack is dropped with it
+struct IntoIter<T> {
+    stack: LockFreeStack<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+}
+
+// for value in stack { ... } pops everything, top first (LIFO order)
+impl<T> IntoIterator for LockFreeStack<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { stack: self }
+    }
+}
+
+// -----
+
+// Lock-free queue (Michael-Scott)
+
+// The stack is LIFO: the last value pushed is the first one popped
+// Pipelines and task queues want FIFO: first in, first out
+// With a queue, pushes and pops happen at different ends, so we need two atomic pointers:
+    // head                           tail
+    //  ↓                              ↓
+    // [dummy] → [1] → [2] → [3] → [4] → null
+    //  pop here                       push here
+
+// The trick that makes it work is the dummy node (also called a sentinel):
+// - head always points at a dummy whose value is already gone (or never existed)
+// - The real first value is in head.next
+// - The queue is empty when head.next is null
+// Because there is always at least one node, head and tail are never null,
+// and a push (which only touches tail) and a pop (which only touches head) don't fight over the same pointer
+
+// Push (enqueue) is two steps:
+// 1. CAS tail.next from null to the new node (this is the step that actually adds the value)
+// 2. CAS tail from the old tail to the new node (moving tail forward)
+// Between step 1 and step 2 tail is "lagging" one node behind
+// Any thread that sees tail.next != null helps by moving tail forward itself, so nobody has to wait for the pusher
+
+// Pop (dequeue):
+// 1. Read head and head.next
+// 2. If head.next is null, the queue is empty
+// 3. CAS head from the old dummy to head.next - the node that held the first value becomes the new dummy
+// 4. Move the value out of the new dummy and retire the old one
+
+// Queue nodes are different from stack nodes:
+// - value is MaybeUninit because the dummy node has no value (the very first dummy never had one)
+// - next is an AtomicPtr because pushers CAS it directly (in the stack only top was atomic)
+struct QueueNode<T> {
+    value: MaybeUninit<T>,
+    next: AtomicPtr<QueueNode<T>>,
+}
+
+impl<T> QueueNode<T> {
+    fn new(value: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(QueueNode {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+struct LockFreeQueue<T> {
+    head: AtomicPtr<QueueNode<T>>, // Always the dummy node, pop from here
+    tail: AtomicPtr<QueueNode<T>>, // Last node (or one behind it), push here
+
+    // Number of values, kept on the side because counting nodes would mean walking the list
+    // It is bumped before a value is linked in and lowered after it is unlinked,
+    // so it can be a little too high while a push or pop is in flight, but never goes below zero
+    len: AtomicUsize,
+
+    retry_count: AtomicUsize,
+
+    // Both push and pop dereference nodes other threads may unlink, so both use hazard pointers
+    hazards: HazardDomain<QueueNode<T>>,
+
+    // Same reason as in LockFreeStack
+    _marker: PhantomData<*mut QueueNode<T>>,
+}
+
+unsafe impl<T: Send> Send for LockFreeQueue<T> {}
+unsafe impl<T: Send> Sync for LockFreeQueue<T> {}
+
+impl<T> LockFreeQueue<T> {
+    // Create an empty queue: a single dummy node that both head and tail point at
+    fn new() -> Self {
+        let dummy = QueueNode::new(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(dummy),
+            tail: AtomicPtr::new(dummy),
+            len: AtomicUsize::new(0),
+            retry_count: AtomicUsize::new(0),
+            hazards: HazardDomain::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    // Add a value at the back of the queue
+    fn push(&self, value: T) {
+        let new_node = QueueNode::new(MaybeUninit::new(value));
+        self.len.fetch_add(1, Ordering::Relaxed);
+
+        let record = self.hazards.acquire();
+        loop {
+            // Step 1: Load and protect the tail -----
+
+            // Same pattern as the stack's pop: publish the hazard, then check tail didn't move
+            // If it didn't, the node can't have been retired yet
+            let tail = self.tail.load(Ordering::Acquire);
+            record.protect(0, tail);
+            if self.tail.load(Ordering::SeqCst) != tail {
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            // Step 2: Look at what comes after the tail -----
+
+            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+
+            if next.is_null() {
+                // Step 3: tail really is the last node, try to link our node after it -----
+
+                // Only one pusher can win this CAS, the rest see next != null on their next try
+                let linked = unsafe {
+                    (*tail).next.compare_exchange(
+                        ptr::null_mut(),
+                        new_node,
+                        Ordering::Release,
+                        Ordering::Acquire,
+                    )
+                };
+                if linked.is_ok() {
+                    // Step 4: Swing tail to our node -----
+
+                    // If this fails, someone already helped us, which is fine
+                    let _ = self.tail.compare_exchange(tail, new_node, Ordering::Release, Ordering::Relaxed);
+                    break;
+                }
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+            } else {
+                // tail is lagging behind: another push linked a node but hasn't moved tail yet
+                // Help it along, then retry
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        record.release();
+    }
+
+    // Take the value at the front of the queue
+    fn pop(&self) -> Option<T> {
+        let record = self.hazards.acquire();
+
+        let result = loop {
+            // Step 1: Load and protect the dummy (head) -----
+
+            let head = self.head.load(Ordering::Acquire);
+            record.protect(0, head);
+            if self.head.load(Ordering::SeqCst) != head {
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            // Step 2: Load and protect the first real node (head.next) -----
+
+            // We will read the value out of it, so it must not be freed under us either
+            // Checking head again is enough: while head hasn't moved, next can't have been popped and retired
+            let next = unsafe { (*head).next.load(Ordering::Acquire) };
+            record.protect(1, next);
+            if self.head.load(Ordering::SeqCst) != head {
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            // Step 3: Empty? -----
+
+            if next.is_null() {
+                break None;
+            }
+
+            // Step 4: Don't let head overtake tail -----
+
+            // If head == tail but head.next exists, a push is half done (tail is lagging)
+            // Help it first, otherwise tail would point at a node we are about to retire
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                self.retry_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            // Step 5: Unlink the dummy -----
+
+            // next becomes the new dummy
+            match self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::Acquire) {
+                Ok(_) => {
+                    // Step 6: Move the value out of the new dummy -----
+
+                    // Only the thread that won the CAS reads it, and MaybeUninit makes sure nobody drops it again
+                    let value = unsafe { ptr::read((*next).value.as_ptr()) };
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+
+                    // Step 7: Retire the old dummy -----
+
+                    record.clear();
+                    self.hazards.retire(record, head);
+                    break Some(value);
+                }
+                Err(_) => {
+                    self.retry_count.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+        };
+
+        record.release();
+        result
+    }
+
+    // Empty when the dummy has nothing after it
+    // We still need a hazard, because head could be popped and freed while we read head.next
+    fn is_empty(&self) -> bool {
+        let record = self.hazards.acquire();
+        let empty = loop {
+            let head = self.head.load(Ordering::Acquire);
+            record.protect(0, head);
+            if self.head.load(Ordering::SeqCst) == head {
+                break unsafe { (*head).next.load(Ordering::Acquire) }.is_null();
+            }
+        };
+        record.release();
+        empty
+    }
+
+    // Approximate number of values in the queue
+    // Exact when no push or pop is in progress, otherwise a snapshot that may already be out of date
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn retry_count(&self) -> usize {
+        self.retry_count.load(Ordering::Relaxed)
+    }
+
+    fn reclaimed_count(&self) -> usize {
+        self.hazards.reclaimed_count()
+    }
+}
+
+// Same idea as the stack's Drop
+// head is the dummy, so its value is skipped; every node after it still owns a value
+impl<T> Drop for LockFreeQueue<T> {
+    fn drop(&mut self) {
+        let dummy = unsafe { Box::from_raw(*self.head.get_mut()) };
+        let mut node = dummy.next.load(Ordering::Relaxed);
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next.load(Ordering::Relaxed);
+            unsafe {
+                boxed.value.assume_init_drop();
+            }
+        }
+    }
+}
+
+// Stress test for the reclamation scheme
+// Every thread interleaves push and pop, so nodes are retired and freed while other threads are still inside pop()
+// Values are Box<usize> so that a use-after-free or double free of a value is a real heap error, not just a wrong number
+
+// Run it under Miri to have every memory access checked
+// This file is a standalone main.rs with no manifest, so drop it into a throwaway crate first (it only uses std):
+//   rustup +nightly component add miri
+//   cargo new --bin hp_stress
+//   cp concurrency/problem_15/main.rs hp_stress/src/main.rs
+//   cd hp_stress && cargo +nightly miri run
+// Under cfg!(miri) main() runs only this test, since Miri is very slow and the other demos would take forever
+// The workload is shrunk under Miri as well
+// If pop() freed the node straight after the CAS instead of retiring it, a thread still in Step 4 would read freed memory,
+// which is exactly the kind of access Miri stops on
+fn reclamation_stress_test() {
+    let (num_threads, iterations) = if cfg!(miri) { (3, 40) } else { (8, 20_000) };
+
+    let stack = Arc::new(LockFreeStack::<Box<usize>>::new());
+    let popped_total = Arc::new(AtomicUsize::new(0));
+    let popped_count = Arc::new(AtomicUsize::new(0));
+    // All threads start at the same moment, so they actually overlap instead of running one after another
+    let start = Arc::new(Barrier::new(num_threads));
+
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+    for thread_id in 0..num_threads {
+        let stack = Arc::clone(&stack);
+        let popped_total = Arc::clone(&popped_total);
+        let popped_count = Arc::clone(&popped_count);
+        let start = Arc::clone(&start);
+        handles.push(thread::spawn(move || {
+            start.wait();
+            for i in 0..iterations {
+                stack.push(Box::new(thread_id * iterations + i));
+                // Pop every time, and every other time pop twice, so the stack keeps emptying out
+                // and threads race on the same few nodes near the top
+                if let Some(value) = stack.pop() {
+                    popped_total.fetch_add(*value, Ordering::Relaxed);
+                    popped_count.fetch_add(1, Ordering::Relaxed);
+                }
+                if i % 2 == 1 {
+                    if let Some(value) = stack.pop() {
+                        popped_total.fetch_add(*value, Ordering::Relaxed);
+                        popped_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Whatever is left over gets popped on the main thread
+    let mut leftover = 0;
+    while let Some(value) = stack.pop() {
+        popped_total.fetch_add(*value, Ordering::Relaxed);
+        leftover += 1;
+    }
+
+    // Every value 0..num_threads * iterations was pushed exactly once, so the popped ones must add up to the same sum
+    let total = num_threads * iterations;
+    let expected: usize = (0..total).sum();
+    let popped = popped_total.load(Ordering::Relaxed);
+    let popped_while_running = popped_count.load(Ordering::Relaxed);
+
+    println!("\n=== Reclamation stress test ===");
+    println!("Threads: {}, pushes per thread: {}", num_threads, iterations);
+    println!("Popped after join: {}", leftover);
+    println!("Nodes reclaimed while running: {}", stack.reclaimed_count());
+    println!("Total CAS retries: {}", stack.retry_count());
+
+    // Assert rather than print, so a lost or duplicated value fails the run (and a Miri run) instead of scrolling past
+    assert_eq!(popped, expected, "popped values do not add up to the pushed ones");
+    // Whatever the threads did not pop must be exactly what was left for the main thread
+    assert_eq!(leftover, total - popped_while_running, "leftover count does not match pushes minus pops");
+    println!("✓ Every value was popped exactly once");
+    // The remaining retired nodes are freed here when the last Arc is dropped
+}
+
+// A value that counts how many times it has been dropped
+// If teardown leaks, the count comes out too low; if it double drops, too high
+struct DropCounter {
+    drops: Arc<AtomicUsize>,
+}
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn check_drops(label: &str, drops: &AtomicUsize, expected: usize) {
+    let actual = drops.load(Ordering::Relaxed);
+    if actual == expected {
+        println!("✓ {}: {} drops", label, actual);
+    } else {
+        println!("✗ {}: {} drops, expected {} (bug!)", label, actual, expected);
+    }
+}
+
+// Check that every value pushed is dropped exactly once, whichever way the stack is emptied
+fn teardown_demo() {
+    println!("\n=== Teardown ===");
+
+    // 1. Values still on the stack are dropped with the stack
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = LockFreeStack::new();
+    for _ in 0..100 {
+        stack.push(DropCounter { drops: Arc::clone(&drops) });
+    }
+    drop(stack);
+    check_drops("Dropping a full stack", &drops, 100);
+
+    // 2. Popped values are dropped by whoever popped them, the rest by the stack
+    // The popped nodes sit on the retired list, and freeing them must not drop their value again
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = LockFreeStack::new();
+    for _ in 0..100 {
+        stack.push(DropCounter { drops: Arc::clone(&drops) });
+    }
+    for _ in 0..30 {
+        drop(stack.pop());
+    }
+    check_drops("After popping 30", &drops, 30);
+    drop(stack);
+    check_drops("Dropping the rest", &drops, 100);
+
+    // 3. drain() hands every value to the caller and leaves an empty, usable stack
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = LockFreeStack::new();
+    for _ in 0..50 {
+        stack.push(DropCounter { drops: Arc::clone(&drops) });
+    }
+    let drained: Vec<DropCounter> = stack.drain().collect();
+    println!("Drained {} values, stack is empty: {}", drained.len(), stack.is_empty());
+    check_drops("Before dropping the drained values", &drops, 0);
+    drop(drained);
+    check_drops("After dropping the drained values", &drops, 50);
+    drop(stack);
+    check_drops("Dropping the drained stack", &drops, 50);
+
+    // 4. into_iter() pops in LIFO order, and dropping it half way drops the rest with the stack
+    let stack = LockFreeStack::new();
+    for i in 1..=5 {
+        stack.push(i);
+    }
+    let order: Vec<i32> = stack.into_iter().collect();
+    println!("into_iter order: {:?}", order);
+
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = LockFreeStack::new();
+    for _ in 0..10 {
+        stack.push(DropCounter { drops: Arc::clone(&drops) });
+    }
+    let mut iter = stack.into_iter();
+    let first_three: Vec<DropCounter> = iter.by_ref().take(3).collect();
+    drop(iter);
+    check_drops("Dropping into_iter after taking 3", &drops, 7);
+    drop(first_three);
+    check_drops("Dropping the 3 taken", &drops, 10);
+
+    // Send/Sync are only implemented for T: Send, so this does not compile:
+    // let stack = Arc::new(LockFreeStack::<std::rc::Rc<i32>>::new());
+    // thread::spawn(move || stack.pop());
+}
+
+// The TaskQueue from problem_10, with the Mutex<VecDeque<Task>> swapped for a LockFreeQueue<Task>
+// add_task/get_task keep the same signatures, so the workers in problem_10 don't change at all
+#[derive(Debug)]
+enum Task {
+    Process { id: usize, value: i32 },
+    Shutdown,
+}
+
+struct TaskQueue {
+    tasks: LockFreeQueue<Task>, // was: Mutex<VecDeque<Task>>
+    total_completed: AtomicUsize,
+}
+
+impl TaskQueue {
+    fn new() -> Self {
+        Self {
+            tasks: LockFreeQueue::new(),
+            total_completed: AtomicUsize::new(0),
+        }
+    }
+
+    fn add_task(&self, task: Task) {
+        self.tasks.push(task); // was: self.tasks.lock().unwrap().push_back(task)
+    }
+
+    fn get_task(&self) -> Option<Task> {
+        self.tasks.pop() // was: self.tasks.lock().unwrap().pop_front()
+    }
+
+    fn mark_completed(&self) {
+        self.total_completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn completed_count(&self) -> usize {
+        self.total_completed.load(Ordering::SeqCst)
+    }
+}
+
+// Multiple producers and multiple consumers on one queue
+fn queue_demo() {
+    println!("\n=== Lock-free queue (Michael-Scott) ===");
+
+    // Single thread: values come out in the order they went in
+    let queue = LockFreeQueue::new();
+    for i in 1..=5 {
+        queue.push(i);
+    }
+    println!("len after 5 pushes: {}", queue.len());
+    let order: Vec<i32> = std::iter::from_fn(|| queue.pop()).collect();
+    println!("pop order: {:?} (FIFO), is_empty: {}", order, queue.is_empty());
+
+    // 4 producers push (producer, sequence number), 4 consumers pop
+    // Across producers the order is interleaved, but each consumer must see any one producer's values in increasing order
+    let num_producers = 4;
+    let num_consumers = 4;
+    let per_producer = 20_000;
+    let total = num_producers * per_producer;
+
+    let queue = Arc::new(LockFreeQueue::<(usize, usize)>::new());
+    let consumed = Arc::new(AtomicUsize::new(0));
+    let start = Arc::new(Barrier::new(num_producers + num_consumers));
+
+    let mut producers: Vec<JoinHandle<()>> = Vec::new();
+    for producer in 0..num_producers {
+        let queue = Arc::clone(&queue);
+        let start = Arc::clone(&start);
+        producers.push(thread::spawn(move || {
+            start.wait();
+            for seq in 0..per_producer {
+                queue.push((producer, seq));
+            }
+        }));
+    }
+
+    let mut consumers: Vec<JoinHandle<bool>> = Vec::new();
+    for _ in 0..num_consumers {
+        let queue = Arc::clone(&queue);
+        let consumed = Arc::clone(&consumed);
+        let start = Arc::clone(&start);
+        consumers.push(thread::spawn(move || {
+            start.wait();
+            // Last sequence number seen from each producer (None = nothing yet)
+            let mut last_seen: Vec<Option<usize>> = vec![None; num_producers];
+            let mut in_order = true;
+            while consumed.load(Ordering::Relaxed) < total {
+                match queue.pop() {
+                    Some((producer, seq)) => {
+                        if let Some(last) = last_seen[producer] {
+                            in_order &= seq > last;
+                        }
+                        last_seen[producer] = Some(seq);
+                        consumed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+            in_order
+        }));
+    }
+
+    for handle in producers {
+        handle.join().unwrap();
+    }
+    let all_in_order = consumers.into_iter().all(|handle| handle.join().unwrap());
+
+    println!("Producers: {}, consumers: {}, values: {}", num_producers, num_consumers, total);
+    println!("Consumed: {}", consumed.load(Ordering::Relaxed));
+    println!("Total CAS retries: {}", queue.retry_count());
+    println!("Nodes reclaimed while running: {}", queue.reclaimed_count());
+    println!("len after draining: {}", queue.len());
+    if all_in_order && queue.is_empty() {
+        println!("✓ Every value consumed once, per-producer FIFO order kept");
+    } else {
+        println!("✗ Queue lost values or reordered a producer (bug!)");
+    }
+
+    // problem_10's worker loop, unchanged, running on the lock-free TaskQueue
+    let task_queue = Arc::new(TaskQueue::new());
+    let num_workers = 3;
+    for id in 0..10 {
+        task_queue.add_task(Task::Process { id, value: id as i32 * 10 });
+    }
+    for _ in 0..num_workers {
+        task_queue.add_task(Task::Shutdown);
+    }
+
+    let mut workers: Vec<JoinHandle<()>> = Vec::new();
+    for _ in 0..num_workers {
+        let task_queue = Arc::clone(&task_queue);
+        workers.push(thread::spawn(move || loop {
+            match task_queue.get_task() {
+                Some(Task::Process { id, value }) => {
+                    // Check the task arrived intact before counting it
+                    if value == id as i32 * 10 {
+                        task_queue.mark_completed();
+                    }
+                }
+                Some(Task::Shutdown) => break,
+                None => thread::yield_now(),
+            }
+        }));
+    }
+    for handle in workers {
+        handle.join().unwrap();
+    }
+    println!("TaskQueue on LockFreeQueue: {} tasks completed", task_queue.completed_count());
+}
+
+// Run the same push/pop-heavy workload on a plain stack and on one with an elimination array
+// Returns how long it took and whether every pushed value came back out exactly once
+fn run_contended(stack: Arc<LockFreeStack<usize>>, num_threads: usize, iterations: usize) -> (u128, bool) {
+    let popped_total = Arc::new(AtomicUsize::new(0));
+    let start = Arc::new(Barrier::new(num_threads));
+    let started = Instant::now();
+
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+    for thread_id in 0..num_threads {
+        let stack = Arc::clone(&stack);
+        let popped_total = Arc::clone(&popped_total);
+        let start = Arc::clone(&start);
+        handles.push(thread::spawn(move || {
+            start.wait();
+            // Half the threads push first, the other half pop first, so pushes and pops keep colliding
+            for i in 0..iterations {
+                let value = thread_id * iterations + i;
+                if thread_id % 2 == 0 {
+                    stack.push(value);
+                }
+                if let Some(popped) = stack.pop() {
+                    popped_total.fetch_add(popped, Ordering::Relaxed);
+                }
+                if thread_id % 2 == 1 {
+                    stack.push(value);
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = started.elapsed().as_micros();
+
+    for value in stack.drain() {
+        popped_total.fetch_add(value, Ordering::Relaxed);
+    }
+    let expected: usize = (0..num_threads * iterations).sum();
+    (elapsed, popped_total.load(Ordering::Relaxed) == expected)
+}
+
+fn elimination_demo() {
+    println!("\n=== Elimination backoff ===");
+    let (num_threads, iterations) = (8, 50_000);
+
+    let plain = Arc::new(LockFreeStack::new());
+    let (plain_micros, plain_ok) = run_contended(Arc::clone(&plain), num_threads, iterations);
+
+    let eliminating = Arc::new(LockFreeStack::with_elimination(num_threads / 2));
+    let (elim_micros, elim_ok) = run_contended(Arc::clone(&eliminating), num_threads, iterations);
+
+    for (name, stack, micros, ok) in [
+        ("plain", &plain, plain_micros, plain_ok),
+        ("elimination", &eliminating, elim_micros, elim_ok),
+    ] {
+        let stats = stack.retry_stats();
+        println!(
+            "{:<12} {:>8}µs  CAS retries: {:>6}  eliminations: {:>6}  backoff spins: {:>8}  {}",
+            name,
+            micros,
+            stats.cas_retries,
+            stats.eliminations,
+            stats.backoff_spins,
+            if ok { "✓ all values accounted for" } else { "✗ values lost (bug!)" }
+        );
+    }
+    // On a machine with few cores the threads barely overlap, so expect few retries and few eliminations
+    // With many cores the plain stack's retries climb fast, and the elimination stack turns many of them into trades
 }
 
 fn main() {
+    // Miri only needs the reclamation test, the other demos are far too slow to interpret
+    if cfg!(miri) {
+        reclamation_stress_test();
+        return;
+    }
     
     // Create a stack wrapped in Arc so multiple threads can share it
     let stack = Arc::new(LockFreeStack::<i32>::new());
@@ -568,4 +1686,9 @@ fn main() {
     } else {
         println!("✗ Stack still has elements (bug!)");
     }
+
+    reclamation_stress_test();
+    teardown_demo();
+    queue_demo();
+    elimination_demo();
 }
@@ -8,6 +8,65 @@
     // start/exit hooks for threads
 // We are building a configuration object that collects our preferences, then constructs the thread pool with all those settings
 
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// A job is a boxed closure that can be called once and sent to a worker thread
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// The payload a panic carries (usually the &str or String given to panic!())
+type PanicPayload = Box<dyn Any + Send + 'static>;
+
+// The hooks are shared by every worker thread, so they live behind an Arc
+// Fn (not FnMut) since several workers can call the same hook at the same time
+// Send + Sync since the hook itself is shared across threads
+type StartHandler = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+type ExitHandler = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+type PanicHandler = Arc<dyn Fn(PanicPayload) + Send + Sync + 'static>;
+
+// The naming closure only ever runs on the thread calling .build(), once per worker
+// So it can be FnMut (it's allowed to keep its own state) and doesn't need Send or Sync
+type ThreadName = Box<dyn FnMut(usize) -> String + 'static>;
+
+// Things that can go wrong when building the pool
+#[derive(Debug)]
+enum BuildError {
+    // A pool with zero workers would accept jobs and never run them
+    ZeroThreads,
+    // The operating system refused to create a thread (e.g. the stack size was too big)
+    Spawn(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::ZeroThreads => write!(f, "Thread pool needs at least one thread"),
+            BuildError::Spawn(error) => write!(f, "Failed to spawn worker thread: {}", error),
+        }
+    }
+}
+
+// Display + Debug is all std::error::Error needs, so callers can box it or use ? into Box<dyn Error>
+// source() points at the io::Error underneath, for callers that walk the error chain
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::ZeroThreads => None,
+            BuildError::Spawn(error) => Some(error),
+        }
+    }
+}
+
+// This lets us use ? on the io::Result returned by thread::Builder::spawn()
+impl From<io::Error> for BuildError {
+    fn from(error: io::Error) -> Self {
+        BuildError::Spawn(error)
+    }
+}
 
 struct ThreadPoolBuilder {
     // We are using Option for the builder fields to disinguish between "not set" and "set to something specific"
@@ -28,6 +87,19 @@ struct ThreadPoolBuilder {
     // This memory is organized as a stack (LIFO - last in, first out)
     stack_size: Option<usize>,
 
+    // Closure that gets the worker index and returns the thread's name
+    // Names show up in panic messages and debuggers, which makes it much easier to tell workers apart
+    thread_name: Option<ThreadName>,
+
+    // Hooks that run on the worker thread itself
+    // start_handler runs once before the worker takes its first job, exit_handler runs once after its last job
+    start_handler: Option<StartHandler>,
+    exit_handler: Option<ExitHandler>,
+
+    // Called (on the worker thread) with the payload of any job that panics
+    // The worker keeps running afterwards either way
+    panic_handler: Option<PanicHandler>,
+
     // Refresher notes -----
     
     // Stack:
@@ -76,6 +148,10 @@ impl ThreadPoolBuilder {
         Self {
             num_threads: None,
             stack_size: None,
+            thread_name: None,
+            start_handler: None,
+            exit_handler: None,
+            panic_handler: None,
         }
     }
 
@@ -101,11 +177,45 @@ impl ThreadPoolBuilder {
         self
     }
 
+    // The closure methods take a generic F instead of a Box so the caller can just pass a plain closure
+    // We do the boxing ourselves: .thread_name(|i| format!("worker-{}", i))
+    fn thread_name<F>(mut self, name: F) -> Self
+    where
+        F: FnMut(usize) -> String + 'static,
+    {
+        self.thread_name = Some(Box::new(name));
+        self
+    }
+
+    fn start_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.start_handler = Some(Arc::new(handler));
+        self
+    }
+
+    fn exit_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.exit_handler = Some(Arc::new(handler));
+        self
+    }
+
+    fn panic_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(PanicPayload) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
     // The final .build() method will return an instance of ThreadPool with the values provided
-    // In this example, we do not actually have a ThreadPool struct since this is just a demonstration
+    // It returns a Result since spawning threads can fail (and zero threads is not a valid pool)
     // We consume self here so that the builder is destroyed and all of its data is moved into ThreadPool
     // Thus, it can no longer be used anymore
-    fn build(self) -> ThreadPool {
+    fn build(mut self) -> Result<ThreadPool, BuildError> {
         // build() consumes self (takes ownership without returning it)
         // This is by design:
             //   1. Prevents calling .build() twice
@@ -127,12 +237,112 @@ impl ThreadPoolBuilder {
         // .unwrap_or(value) - default is evaluated immediately (use for cheap defaults like numbers)
         // .unwrap_or_else(|| closure) - default computed lazily (use for expensive defaults)
 
-        // Now you can call ThreadPool::new() or build it directly
-        // You could either:
-            // 1. Have ThreadPool::new() accept these parameters
-            // 2. Or build the ThreadPool directly with these values
-        
-        ThreadPool::new(num_threads, stack_size)
+        if num_threads == 0 {
+            return Err(BuildError::ZeroThreads);
+        }
+
+        // Same channel setup as a regular thread pool
+        // One sender (the pool) and one receiver shared by every worker through Arc<Mutex<>>
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut workers = Vec::with_capacity(num_threads);
+
+        for index in 0..num_threads {
+            // thread::spawn() always uses the default settings
+            // thread::Builder is the std version of what we are building here - it lets us set a name and stack size first
+            let mut builder = thread::Builder::new().stack_size(stack_size);
+
+            // .as_mut() gives us Option<&mut ThreadName> so we can call the FnMut without moving it out of self
+            if let Some(name) = self.thread_name.as_mut() {
+                builder = builder.name(name(index));
+            }
+
+            let receiver = Arc::clone(&receiver);
+            // Cloning an Option<Arc<...>> clones the Arc inside (if there is one)
+            let start_handler = self.start_handler.clone();
+            let exit_handler = self.exit_handler.clone();
+            let panic_handler = self.panic_handler.clone();
+
+            // Unlike thread::spawn(), Builder::spawn() returns io::Result<JoinHandle>
+            // The ? converts the io::Error into a BuildError using our From impl
+            // If this fails part way through, the workers we already spawned see the channel close (sender is dropped) and exit
+            let handle = builder.spawn(move || {
+                if let Some(handler) = &start_handler {
+                    handler(index);
+                }
+
+                loop {
+                    let recv = receiver.lock().unwrap().recv();
+                    match recv {
+                        Ok(job) => {
+                            // Catch the panic so one bad job doesn't kill the worker
+                            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                                if let Some(handler) = &panic_handler {
+                                    handler(payload);
+                                }
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                }
+
+                if let Some(handler) = &exit_handler {
+                    handler(index);
+                }
+            })?;
+
+            workers.push(handle);
+        }
+
+        Ok(ThreadPool {
+            sender: Some(sender),
+            workers,
+        })
+    }
+}
+
+// The pool the builder produces
+struct ThreadPool {
+    // Option so Drop can take the sender out and drop it
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            sender.send(Box::new(f)).unwrap();
+        }
+    }
+
+    // Drop does all of the work (see impl Drop for ThreadPool below)
+    // join() just gives that a name, so the caller can make it explicit
+    fn join(self) {
+        drop(self);
+    }
+}
+
+// Without this, a pool that goes out of scope without join() would just detach its workers,
+// and their exit hooks might never run before main returns
+impl Drop for ThreadPool {
+    // Drop the sender so the workers' .recv() fails, then wait for every worker to run its exit hook and finish
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        // Join every worker before reporting anything, so one panicked worker doesn't leave the rest detached
+        let mut panicked = 0;
+        for handle in self.workers.drain(..) {
+            if handle.join().is_err() {
+                panicked += 1;
+            }
+        }
+        // If we are already unwinding, a second panic here would abort the process, so only report worker panics otherwise
+        if panicked > 0 && !thread::panicking() {
+            panic!("{} worker thread(s) panicked", panicked);
+        }
     }
 }
 
@@ -147,13 +357,43 @@ fn main() {
     let pool = ThreadPoolBuilder::new()
         .num_threads(8)
         .stack_size(4 * 1024 * 1024)
-        .build();
-    
+        .build()
+        .unwrap();
+    pool.join();
+
     // Example 2: Only set num_threads (stack_size gets default)
     let pool = ThreadPoolBuilder::new()
         .num_threads(8)
-        .build();
-    
+        .build()
+        .unwrap();
+    pool.join();
+
     // Example 3: Use all defaults
-    let pool = ThreadPoolBuilder::new().build();
-}
\ No newline at end of file
+    let pool = ThreadPoolBuilder::new().build().unwrap();
+    pool.join();
+
+    // Example 4: Names and lifecycle hooks
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(2)
+        .thread_name(|i| format!("worker-{}", i))
+        .start_handler(|i| println!("Worker {} starting on {:?}", i, thread::current().name()))
+        .exit_handler(|i| println!("Worker {} exiting", i))
+        .panic_handler(|payload| {
+            let message = payload.downcast_ref::<&str>().copied().unwrap_or("unknown panic");
+            println!("Job panicked on {:?}: {}", thread::current().name(), message);
+        })
+        .build()
+        .unwrap();
+
+    for i in 0..4 {
+        pool.execute(move || println!("Job {} running on {:?}", i, thread::current().name()));
+    }
+    pool.execute(|| panic!("bad job"));
+    pool.join();
+
+    // Example 5: Zero threads is rejected instead of building a pool that never runs anything
+    match ThreadPoolBuilder::new().num_threads(0).build() {
+        Ok(_) => println!("Built a pool with zero threads?"),
+        Err(error) => println!("Build failed: {}", error),
+    }
+}
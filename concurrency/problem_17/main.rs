@@ -22,7 +22,14 @@
 // The entire point of parallel join is to be able to run 2 functions at the same time instead
 // of one after another
 
-use std::thread;
+use std::any::Any;
+use std::cell::{Cell, UnsafeCell};
+use std::collections::{HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
 
 // Below we are designing the parallel_join() function with trait bounds
 // The trait bounds are:
@@ -112,5 +119,553 @@ where
 // Each parallel_join splits work between a spawned thread and the current thread
 // And nesting them creates the tree of parallel execution
 
+// The problems with parallel_join:
+    // 1. Every call spawns a brand new OS thread
+        // A recursive sum that splits 1,000,000 elements down to 1,000 per piece calls it ~1,000 times
+        // That's ~1,000 threads for a machine with maybe 8 cores - creating them costs more than the work
+    // 2. The 'static bounds mean the closures can't borrow anything
+        // You can't split a &[T] or a &mut [T] in half and hand each half to a closure
+        // You have to wrap everything in Arc and pass indexes around instead
+    // 3. .expect("Thread panicked") throws away the original panic message
+
+// Pool-backed join() -----
+
+// This is how Rayon's join works:
+// A fixed pool of worker threads, each with its own deque (double-ended queue) of jobs
+
+// join(a, b) called on a worker:
+    // 1. Push a onto the BACK of this worker's deque (now other workers can see it)
+    // 2. Run b right here
+    // 3. Pop from the BACK of the deque
+        // Still there? Nobody took it - run a right here too (the common case, no thread involved at all)
+        // Gone? Another worker stole it - help with other jobs until it's done
+    // 4. Return both results
+
+// Idle workers steal from the FRONT of other workers' deques
+    // The front holds the oldest jobs, which are the biggest (top of the recursion tree)
+    // So one steal moves a big chunk of work, and thieves don't fight with the owner at the back
+
+    //   Worker 0 deque: [ sort(0..500k) | sort(0..250k) | sort(0..125k) ]
+    //                     ↑ thieves steal here               ↑ owner pushes/pops here
+
+// The number of threads never changes, however deep the recursion goes
+// And because join() doesn't return until both a and b are done, a and b can borrow from the caller's stack
+
+// A job is a closure living on the stack of whoever called join()
+// Other threads only get a JobRef: a pointer to it plus a function that knows how to run it
+// (the deques need one type for all jobs, so the closure type is erased behind *const ())
+struct JobRef {
+    data: *const (),
+    execute_fn: unsafe fn(*const ()),
+}
+
+// The pointer is only used while the job is guaranteed to be alive (see StackJob)
+unsafe impl Send for JobRef {}
+
+impl JobRef {
+    // Safety: the caller must keep `job` alive (and not move it) until its latch is set
+    // or until it takes the JobRef back out of the deque
+    unsafe fn new<L, F, R>(job: &StackJob<L, F, R>) -> JobRef
+    where
+        L: Latch,
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        JobRef {
+            data: job as *const StackJob<L, F, R> as *const (),
+            execute_fn: StackJob::<L, F, R>::execute,
+        }
+    }
+
+    unsafe fn execute(self) {
+        unsafe { (self.execute_fn)(self.data) }
+    }
+}
+
+// A latch is a one-shot "done" signal
+trait Latch {
+    fn set(&self);
+}
+
+// Workers waiting for a stolen job keep working on other jobs, so they just check a flag
+struct SpinLatch {
+    done: AtomicBool,
+}
+
+impl SpinLatch {
+    fn new() -> SpinLatch {
+        SpinLatch { done: AtomicBool::new(false) }
+    }
+
+    fn probe(&self) -> bool {
+        // Acquire pairs with the Release in set(), so the job's result is visible once we see true
+        self.done.load(Ordering::Acquire)
+    }
+}
+
+impl Latch for SpinLatch {
+    fn set(&self) {
+        // After this store the job's owner may return and free the job - don't touch self after it
+        self.done.store(true, Ordering::Release);
+    }
+}
+
+// Threads outside the pool have nothing else to do, so they sleep on a Condvar
+struct LockLatch {
+    done: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl LockLatch {
+    const fn new() -> LockLatch {
+        LockLatch { done: Mutex::new(false), changed: Condvar::new() }
+    }
+
+    fn wait_and_reset(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.changed.wait(done).unwrap();
+        }
+        *done = false;
+    }
+}
+
+impl Latch for LockLatch {
+    fn set(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done = true;
+        self.changed.notify_all();
+    }
+}
+
+impl<L: Latch> Latch for &L {
+    fn set(&self) {
+        (**self).set()
+    }
+}
+
+// The result of a job: not run yet, a value, or the payload of a panic
+enum JobResult<R> {
+    Pending,
+    Ok(R),
+    Panic(Box<dyn Any + Send>),
+}
+
+// The job itself: the closure, a slot for its result, and a latch to say the result is ready
+// UnsafeCell because the thread that runs the job writes through a shared reference
+struct StackJob<L, F, R> {
+    latch: L,
+    func: UnsafeCell<Option<F>>,
+    result: UnsafeCell<JobResult<R>>,
+}
+
+impl<L, F, R> StackJob<L, F, R>
+where
+    L: Latch,
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    fn new(func: F, latch: L) -> StackJob<L, F, R> {
+        StackJob {
+            latch,
+            func: UnsafeCell::new(Some(func)),
+            result: UnsafeCell::new(JobResult::Pending),
+        }
+    }
+
+    // Called through a JobRef, by whichever thread ended up with the job
+    unsafe fn execute(this: *const ()) {
+        let this = unsafe { &*(this as *const StackJob<L, F, R>) };
+        let func = unsafe { (*this.func.get()).take() }.expect("job executed twice");
+        // A panic in the job must not kill the worker thread - catch it and hand it back to the owner
+        let result = match panic::catch_unwind(AssertUnwindSafe(func)) {
+            Ok(value) => JobResult::Ok(value),
+            Err(payload) => JobResult::Panic(payload),
+        };
+        unsafe { *this.result.get() = result };
+        this.latch.set();
+    }
+
+    // Safety: only after popping our own JobRef back, so no other thread can run it
+    unsafe fn take_func(&self) -> F {
+        unsafe { (*self.func.get()).take() }.expect("job already taken")
+    }
+
+    // Only after the latch is set
+    fn into_result(self) -> thread::Result<R> {
+        match self.result.into_inner() {
+            JobResult::Ok(value) => Ok(value),
+            JobResult::Panic(payload) => Err(payload),
+            JobResult::Pending => unreachable!("job result read before it was set"),
+        }
+    }
+}
+
+// Everything the workers of one pool share
+struct Registry {
+    // One deque per worker - the owner uses the back, thieves use the front
+    deques: Vec<Mutex<VecDeque<JobRef>>>,
+    // Jobs coming from threads outside the pool
+    injector: Mutex<VecDeque<JobRef>>,
+    // Idle workers sleep here
+    sleep: Mutex<()>,
+    wake: Condvar,
+    terminate: AtomicBool,
+}
+
+impl Registry {
+    // Pushers take the sleep lock before notifying, and sleepers re-check for work while holding it
+    // So a worker can never miss a job pushed between "found nothing" and "went to sleep"
+    fn notify(&self) {
+        let _guard = self.sleep.lock().unwrap();
+        self.wake.notify_all();
+    }
+
+    fn inject(&self, job: JobRef) {
+        self.injector.lock().unwrap().push_back(job);
+        self.notify();
+    }
+
+    // Take from the injector first, then from the front of the other workers' deques
+    fn steal(&self, thief: usize) -> Option<JobRef> {
+        if let Some(job) = self.injector.lock().unwrap().pop_front() {
+            return Some(job);
+        }
+        let count = self.deques.len();
+        // Start at the next worker, so all thieves don't hammer worker 0
+        (1..count)
+            .map(|offset| (thief + offset) % count)
+            .find_map(|victim| self.deques[victim].lock().unwrap().pop_front())
+    }
+
+    fn has_work(&self) -> bool {
+        !self.injector.lock().unwrap().is_empty()
+            || self.deques.iter().any(|deque| !deque.lock().unwrap().is_empty())
+    }
+
+    // Run f on one of this pool's workers and wait for the result
+    fn in_worker<F, R>(self: &Arc<Self>, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        let worker = WorkerThread::current();
+        // Already on one of our workers - just run it
+        if !worker.is_null() && Arc::ptr_eq(unsafe { &(*worker).registry }, self) {
+            return f();
+        }
+
+        // The latch is thread-local, not on the stack:
+        // LockLatch::set() still touches the Mutex after waking us up, so it must outlive this call
+        thread_local! {
+            static LOCK_LATCH: LockLatch = const { LockLatch::new() };
+        }
+
+        let result = LOCK_LATCH.with(|latch| {
+            let job = StackJob::new(f, latch);
+            // Safety: we block below until the latch is set, so job stays alive
+            self.inject(unsafe { JobRef::new(&job) });
+            latch.wait_and_reset();
+            job.into_result()
+        });
+        result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+}
+
+// Each worker thread knows its index and its pool through a thread-local pointer
+// That's how join() finds "the current worker's deque" without being passed anything
+struct WorkerThread {
+    index: usize,
+    registry: Arc<Registry>,
+}
+
+thread_local! {
+    static WORKER: Cell<*const WorkerThread> = const { Cell::new(ptr::null()) };
+}
+
+impl WorkerThread {
+    // Null when called from a thread that isn't a pool worker
+    fn current() -> *const WorkerThread {
+        WORKER.with(|worker| worker.get())
+    }
+
+    fn push(&self, job: JobRef) {
+        self.registry.deques[self.index].lock().unwrap().push_back(job);
+        self.registry.notify();
+    }
+
+    fn pop(&self) -> Option<JobRef> {
+        self.registry.deques[self.index].lock().unwrap().pop_back()
+    }
+
+    fn find_work(&self) -> Option<JobRef> {
+        self.pop().or_else(|| self.registry.steal(self.index))
+    }
+
+    // Our job was stolen - instead of blocking, help run other jobs until it's done
+    fn wait_until(&self, latch: &SpinLatch) {
+        while !latch.probe() {
+            match self.find_work() {
+                Some(job) => unsafe { job.execute() },
+                None => thread::yield_now(),
+            }
+        }
+    }
+}
+
+fn worker_main(registry: Arc<Registry>, index: usize) {
+    let worker = WorkerThread { index, registry };
+    WORKER.with(|current| current.set(&worker));
+
+    loop {
+        if let Some(job) = worker.find_work() {
+            unsafe { job.execute() };
+            continue;
+        }
+        let guard = worker.registry.sleep.lock().unwrap();
+        if worker.registry.terminate.load(Ordering::Acquire) {
+            break;
+        }
+        if worker.registry.has_work() {
+            continue;
+        }
+        drop(worker.registry.wake.wait(guard).unwrap());
+    }
+
+    WORKER.with(|current| current.set(ptr::null()));
+}
+
+struct ThreadPool {
+    registry: Arc<Registry>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    fn new(num_threads: usize) -> ThreadPool {
+        assert!(num_threads > 0, "a pool needs at least one thread");
+        let registry = Arc::new(Registry {
+            deques: (0..num_threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            injector: Mutex::new(VecDeque::new()),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            terminate: AtomicBool::new(false),
+        });
+        let handles = (0..num_threads)
+            .map(|index| {
+                let registry = Arc::clone(&registry);
+                thread::Builder::new()
+                    .name(format!("join-worker-{}", index))
+                    .spawn(move || worker_main(registry, index))
+                    .expect("failed to spawn worker")
+            })
+            .collect();
+        ThreadPool { registry, handles }
+    }
+
+    // The pool join() uses when called from outside any pool, created on first use
+    fn global() -> &'static ThreadPool {
+        static GLOBAL: OnceLock<ThreadPool> = OnceLock::new();
+        GLOBAL.get_or_init(|| ThreadPool::new(thread::available_parallelism().map_or(4, |n| n.get())))
+    }
+
+    fn num_threads(&self) -> usize {
+        self.handles.len()
+    }
+
+    // Run f inside this pool, so every join() it makes uses this pool's workers
+    // f doesn't need 'static: we block until it's done
+    fn install<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        self.registry.in_worker(f)
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        {
+            let _guard = self.registry.sleep.lock().unwrap();
+            self.registry.terminate.store(true, Ordering::Release);
+            self.registry.wake.notify_all();
+        }
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+// Same signature as parallel_join, minus all four 'static bounds
+// If a or b panics, we still wait for the other one (it may be borrowing our stack),
+// then re-raise the panic with its original payload - a's if both panicked
+fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let worker = WorkerThread::current();
+    // Not on a worker - move the whole join into the global pool and wait
+    if worker.is_null() {
+        return ThreadPool::global().registry.in_worker(|| join(a, b));
+    }
+    // The worker lives for the whole time its thread is running, which is longer than this call
+    let worker = unsafe { &*worker };
+
+    // 1. Push a, so another worker can steal it
+    let job_a = StackJob::new(a, SpinLatch::new());
+    let job_a_ref = unsafe { JobRef::new(&job_a) };
+    let job_a_data = job_a_ref.data;
+    worker.push(job_a_ref);
+
+    // 2. Run b here - catch a panic so we still wait for a
+    let result_b = panic::catch_unwind(AssertUnwindSafe(b));
+
+    // 3. Get a back
+    let result_a = loop {
+        if job_a.latch.probe() {
+            break job_a.into_result();
+        }
+        match worker.pop() {
+            // Nobody stole it - run it inline, no other thread involved
+            Some(job) if job.data == job_a_data => {
+                let a = unsafe { job_a.take_func() };
+                break panic::catch_unwind(AssertUnwindSafe(a));
+            }
+            // Some other job from our deque - a was stolen, so do useful work while we wait
+            Some(job) => unsafe { job.execute() },
+            // Deque empty - a was stolen, help out until it's done
+            None => {
+                worker.wait_until(&job_a.latch);
+                break job_a.into_result();
+            }
+        }
+    };
+
+    // 4. Both are done - now it's safe to unwind
+    match (result_a, result_b) {
+        (Ok(ra), Ok(rb)) => (ra, rb),
+        (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+    }
+}
+
+// Demos -----
+
+const THRESHOLD: usize = 1_000;
+
+// Recursive sum with the old parallel_join: needs an Arc and index ranges because of 'static
+fn spawn_sum(data: Arc<Vec<u64>>, start: usize, end: usize, spawned: Arc<AtomicUsize>) -> u64 {
+    if end - start <= THRESHOLD {
+        return data[start..end].iter().sum();
+    }
+    let mid = start + (end - start) / 2;
+    spawned.fetch_add(1, Ordering::Relaxed);
+    let (left_data, left_spawned) = (Arc::clone(&data), Arc::clone(&spawned));
+    let (left, right) = parallel_join(
+        move || spawn_sum(left_data, start, mid, left_spawned),
+        move || spawn_sum(data, mid, end, spawned),
+    );
+    left + right
+}
+
+// The same sum with join: just split the slice
+fn pool_sum(data: &[u64], threads: &Mutex<HashSet<thread::ThreadId>>) -> u64 {
+    if data.len() <= THRESHOLD {
+        threads.lock().unwrap().insert(thread::current().id());
+        return data.iter().sum();
+    }
+    let (left, right) = data.split_at(data.len() / 2);
+    let (a, b) = join(|| pool_sum(left, threads), || pool_sum(right, threads));
+    a + b
+}
+
+// Mutable borrows work too: each half of the slice goes to a different closure
+fn quicksort(data: &mut [i32]) {
+    if data.len() <= 32 {
+        data.sort_unstable();
+        return;
+    }
+    let pivot_index = partition(data);
+    let (left, right) = data.split_at_mut(pivot_index);
+    join(|| quicksort(left), || quicksort(&mut right[1..]));
+}
+
+// Lomuto partition around the last element, returns the pivot's final position
+fn partition(data: &mut [i32]) -> usize {
+    let last = data.len() - 1;
+    data.swap(data.len() / 2, last);
+    let mut store = 0;
+    for i in 0..last {
+        if data[i] < data[last] {
+            data.swap(i, store);
+            store += 1;
+        }
+    }
+    data.swap(store, last);
+    store
+}
+
 fn main() {
+    let data: Vec<u64> = (1..=1_000_000).collect();
+    let expected: u64 = data.iter().sum();
+
+    println!("=== parallel_join: a new thread per call ===");
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let total = spawn_sum(Arc::new(data.clone()), 0, data.len(), Arc::clone(&spawned));
+    println!("Sum: {} (expected {})", total, expected);
+    println!("Threads spawned: {}\n", spawned.load(Ordering::Relaxed));
+
+    println!("=== join: a fixed pool of workers ===");
+    let pool = ThreadPool::new(4);
+    let threads = Mutex::new(HashSet::new());
+    let total = pool.install(|| pool_sum(&data, &threads));
+    println!("Sum: {} (expected {})", total, expected);
+    println!(
+        "Distinct threads that ran leaves: {} (pool size {})\n",
+        threads.lock().unwrap().len(),
+        pool.num_threads()
+    );
+
+    println!("=== join with &mut borrows: parallel quicksort ===");
+    let mut numbers: Vec<i32> = (0..200_000).map(|i| (i * 7_919) % 100_003).collect();
+    // Called outside install(), so this one runs on the global pool
+    quicksort(&mut numbers);
+    println!("Sorted: {}\n", numbers.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    println!("=== Panic propagation ===");
+    // Silence the default "thread panicked" message, we print our own
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let a_finished = AtomicBool::new(false);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.install(|| {
+            join(
+                || {
+                    thread::sleep(std::time::Duration::from_millis(50));
+                    a_finished.store(true, Ordering::SeqCst);
+                },
+                || panic!("b failed on purpose"),
+            )
+        })
+    }));
+    panic::set_hook(default_hook);
+
+    match outcome {
+        Ok(_) => println!("No panic?"),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .unwrap_or("<non-string payload>");
+            println!("Caught panic: {:?}", message);
+            println!("a finished before the panic was re-raised: {}", a_finished.load(Ordering::SeqCst));
+        }
+    }
+
+    // The pool survives a panicking job
+    let (x, y) = pool.install(|| join(|| 1 + 1, || 2 + 2));
+    println!("Pool still works after the panic: ({}, {})", x, y);
 }
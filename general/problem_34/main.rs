@@ -32,7 +32,11 @@
 // - Each adapter wraps the previous one
 // - All parallel operations compose through this pattern
 
+use std::iter::Sum;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 // Part 1 - Base Iterator - MyIter -----
 
@@ -392,6 +396,339 @@ impl<T> IntoMyIter<T> for Vec<T> {
 // 2. Extend all iterators using the extension trait pattern
 // 3. Extend Vec to be able to call .my_iter(), also using the extension trait pattern
 
+// Part 6 - Parallel Iterators -----
+
+// Now the Rayon part: data.par_iter().map(...).filter(...).sum()
+// The adapters look the same as before, but the work is split across threads
+
+// How Rayon splits the work:
+// 1. Take the whole input
+// 2. Split it in half
+// 3. Run both halves at the same time with parallel_join (one half on a new thread, one on this thread)
+// 4. Each half splits itself again, until there are enough pieces to keep every core busy
+// 5. Each piece (a "leaf") runs sequentially, with the plain iterator adapters from Part 2-4
+// 6. On the way back up, the two halves' results are combined (sum + sum, vec + vec, ...)
+
+    //                 [1..8]
+    //               /        \
+    //          [1..4]          [5..8]          ← parallel_join
+    //          /    \          /    \
+    //      [1,2]   [3,4]   [5,6]   [7,8]       ← parallel_join, then each leaf runs sequentially
+    //         \     /          \    /
+    //          sum 10            sum 26        ← combine
+    //               \          /
+    //                  sum 36
+
+// Because the left half is always combined before the right half, results come back in input order
+// (collect gives the same Vec as the sequential version, reduce only needs to be associative)
+
+// parallel_join from concurrency/problem_17, with one change:
+// problem_17 uses thread::spawn, which needs 'static closures - but par_iter() borrows the slice
+// thread::scope guarantees the spawned thread is joined before the scope ends, so the closures can borrow
+fn parallel_join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    thread::scope(|s| {
+        // 1. Spawn a new thread to run function a
+        let handle = s.spawn(a);
+        // 2. Run function b on the current thread
+        let result_b = b();
+        // 3. Wait for a (if it panicked, re-raise the panic here)
+        let result_a = handle.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        (result_a, result_b)
+    })
+}
+
+// A parallel iterator only needs to know three things:
+// - base_len(): how many input elements it covers, so we know when to stop splitting
+// - split(): cut itself into two halves that can run on different threads
+// - with_seq(): run sequentially, handing a normal Iterator to `consume`
+// Everything else (the adapters and the terminal methods) is built on top of these
+// Send because the halves move to other threads
+trait ParallelIterator: Sized + Send {
+    // Send because items are produced on one thread and results are combined on another
+    type Item: Send;
+
+    fn base_len(&self) -> usize;
+
+    fn split(self) -> (Self, Self);
+
+    // The sequential iterator is passed as &mut dyn Iterator because every adapter wraps it in a different type
+    // (MapIter, FilterIter, ...), and naming those types here would be impossible with closures inside
+    fn with_seq<R, C>(self, consume: C) -> R
+    where
+        C: FnOnce(&mut dyn Iterator<Item = Self::Item>) -> R;
+
+    // Adapters -----
+    // These are lazy, like my_map and my_filter: they only wrap self
+
+    fn map<F, U>(self, func: F) -> ParMap<Self, F>
+    where
+        F: Fn(Self::Item) -> U + Send + Sync,
+        U: Send,
+    {
+        // Arc so that split() can hand the same function to both halves
+        ParMap { base: self, func: Arc::new(func) }
+    }
+
+    fn filter<P>(self, predicate: P) -> ParFilter<Self, P>
+    where
+        P: Fn(&Self::Item) -> bool + Send + Sync,
+    {
+        ParFilter { base: self, predicate: Arc::new(predicate) }
+    }
+
+    fn flat_map<F, U>(self, func: F) -> ParFlatMap<Self, F>
+    where
+        F: Fn(Self::Item) -> U + Send + Sync,
+        U: IntoIterator,
+        U::Item: Send,
+    {
+        ParFlatMap { base: self, func: Arc::new(func) }
+    }
+
+    // Terminal methods -----
+    // These run the whole chain with drive(): split, run the leaves, combine
+
+    fn for_each<F>(self, func: F)
+    where
+        F: Fn(Self::Item) + Send + Sync,
+    {
+        drive(self, |piece| piece.with_seq(|items| items.for_each(&func)), |_, _| ())
+    }
+
+    // identity() starts every leaf (and is the answer for empty input), op combines two values
+    // op must be associative: op(op(a, b), c) == op(a, op(b, c)), but it does not need to be commutative
+    fn reduce<ID, OP>(self, identity: ID, op: OP) -> Self::Item
+    where
+        ID: Fn() -> Self::Item + Send + Sync,
+        OP: Fn(Self::Item, Self::Item) -> Self::Item + Send + Sync,
+    {
+        drive(
+            self,
+            |piece| piece.with_seq(|items| items.fold(identity(), &op)),
+            &op,
+        )
+    }
+
+    // Sum<Self::Item> sums each leaf, Sum<S> adds up the leaf sums
+    fn sum<S>(self) -> S
+    where
+        S: Send + Sum<Self::Item> + Sum<S>,
+    {
+        drive(
+            self,
+            |piece| piece.with_seq(|items| items.sum::<S>()),
+            |left, right| [left, right].into_iter().sum(),
+        )
+    }
+
+    // Each leaf collects into a Vec, the Vecs are joined left to right, then turned into C
+    // So any C that a normal iterator can collect into works here too (Vec, String, HashSet, ...)
+    fn collect<C>(self) -> C
+    where
+        C: FromIterator<Self::Item>,
+    {
+        let items: Vec<Self::Item> = drive(
+            self,
+            |piece| piece.with_seq(|items| items.collect::<Vec<_>>()),
+            |mut left, mut right| {
+                left.append(&mut right);
+                left
+            },
+        );
+        items.into_iter().collect()
+    }
+}
+
+// Split about twice as many pieces as there are cores, so a core that finishes early can pick up another piece
+fn drive<P, R, L, C>(iter: P, leaf: L, combine: C) -> R
+where
+    P: ParallelIterator,
+    R: Send,
+    L: Fn(P) -> R + Sync,
+    C: Fn(R, R) -> R + Sync,
+{
+    let pieces = thread::available_parallelism().map_or(4, |n| n.get()) * 2;
+    bridge(iter, pieces, &leaf, &combine)
+}
+
+// The recursive splitting from the diagram above
+fn bridge<P, R, L, C>(iter: P, pieces: usize, leaf: &L, combine: &C) -> R
+where
+    P: ParallelIterator,
+    R: Send,
+    L: Fn(P) -> R + Sync,
+    C: Fn(R, R) -> R + Sync,
+{
+    // Small enough - run this piece sequentially
+    if pieces <= 1 || iter.base_len() <= 1 {
+        return leaf(iter);
+    }
+    let (left, right) = iter.split();
+    let (left_result, right_result) = parallel_join(
+        || bridge(left, pieces / 2, leaf, combine),
+        || bridge(right, pieces - pieces / 2, leaf, combine),
+    );
+    combine(left_result, right_result)
+}
+
+// The base parallel iterator: borrows a slice and yields &T, like .iter()
+// T: Sync because several threads read the slice at the same time
+struct ParIter<'a, T> {
+    slice: &'a [T],
+}
+
+impl<'a, T: Sync> ParallelIterator for ParIter<'a, T> {
+    type Item = &'a T;
+
+    fn base_len(&self) -> usize {
+        self.slice.len()
+    }
+
+    fn split(self) -> (Self, Self) {
+        let (left, right) = self.slice.split_at(self.slice.len() / 2);
+        (ParIter { slice: left }, ParIter { slice: right })
+    }
+
+    fn with_seq<R, C>(self, consume: C) -> R
+    where
+        C: FnOnce(&mut dyn Iterator<Item = Self::Item>) -> R,
+    {
+        consume(&mut self.slice.iter())
+    }
+}
+
+// The adapters only add their step to the sequential iterator of the piece they wrap
+// Splitting an adapter means splitting what it wraps and sharing the function between both halves
+
+struct ParMap<I, F> {
+    base: I,
+    func: Arc<F>,
+}
+
+impl<I, F, U> ParallelIterator for ParMap<I, F>
+where
+    I: ParallelIterator,
+    F: Fn(I::Item) -> U + Send + Sync,
+    U: Send,
+{
+    type Item = U;
+
+    fn base_len(&self) -> usize {
+        self.base.base_len()
+    }
+
+    fn split(self) -> (Self, Self) {
+        let (left, right) = self.base.split();
+        (
+            ParMap { base: left, func: Arc::clone(&self.func) },
+            ParMap { base: right, func: self.func },
+        )
+    }
+
+    fn with_seq<R, C>(self, consume: C) -> R
+    where
+        C: FnOnce(&mut dyn Iterator<Item = Self::Item>) -> R,
+    {
+        let func = self.func;
+        // my_map from Part 4 - &mut dyn Iterator is an Iterator itself, so it gets MyIteratorExt too
+        self.base.with_seq(|items| consume(&mut items.my_map(|item| func(item))))
+    }
+}
+
+struct ParFilter<I, P> {
+    base: I,
+    predicate: Arc<P>,
+}
+
+impl<I, P> ParallelIterator for ParFilter<I, P>
+where
+    I: ParallelIterator,
+    P: Fn(&I::Item) -> bool + Send + Sync,
+{
+    type Item = I::Item;
+
+    // The length before filtering - we can't know how many pass without running the predicate
+    fn base_len(&self) -> usize {
+        self.base.base_len()
+    }
+
+    fn split(self) -> (Self, Self) {
+        let (left, right) = self.base.split();
+        (
+            ParFilter { base: left, predicate: Arc::clone(&self.predicate) },
+            ParFilter { base: right, predicate: self.predicate },
+        )
+    }
+
+    fn with_seq<R, C>(self, consume: C) -> R
+    where
+        C: FnOnce(&mut dyn Iterator<Item = Self::Item>) -> R,
+    {
+        let predicate = self.predicate;
+        self.base.with_seq(|items| consume(&mut items.my_filter(|item| predicate(item))))
+    }
+}
+
+struct ParFlatMap<I, F> {
+    base: I,
+    func: Arc<F>,
+}
+
+impl<I, F, U> ParallelIterator for ParFlatMap<I, F>
+where
+    I: ParallelIterator,
+    F: Fn(I::Item) -> U + Send + Sync,
+    U: IntoIterator,
+    U::Item: Send,
+{
+    type Item = U::Item;
+
+    fn base_len(&self) -> usize {
+        self.base.base_len()
+    }
+
+    fn split(self) -> (Self, Self) {
+        let (left, right) = self.base.split();
+        (
+            ParFlatMap { base: left, func: Arc::clone(&self.func) },
+            ParFlatMap { base: right, func: self.func },
+        )
+    }
+
+    fn with_seq<R, C>(self, consume: C) -> R
+    where
+        C: FnOnce(&mut dyn Iterator<Item = Self::Item>) -> R,
+    {
+        let func = self.func;
+        // We never wrote a my_flat_map, so this uses the standard one
+        self.base.with_seq(|items| consume(&mut items.flat_map(|item| func(item))))
+    }
+}
+
+// Same extension trait pattern as IntoMyIter: give slices and Vecs a .par_iter()
+// It borrows (&self) instead of consuming, like .iter()
+trait IntoParIter<T> {
+    fn par_iter(&self) -> ParIter<'_, T>;
+}
+
+impl<T: Sync> IntoParIter<T> for [T] {
+    fn par_iter(&self) -> ParIter<'_, T> {
+        ParIter { slice: self }
+    }
+}
+
+impl<T: Sync> IntoParIter<T> for Vec<T> {
+    fn par_iter(&self) -> ParIter<'_, T> {
+        ParIter { slice: self.as_slice() }
+    }
+}
+
 fn main() {
     println!("=== Problem A: Trait-Based Iterator Chain ===\n");
 
@@ -497,4 +834,61 @@ fn main() {
     println!("Now collecting (this triggers execution):");
     let result: Vec<i32> = iter.collect();
     println!("Final result: {:?}\n", result);
+
+    println!("=== Problem B: Parallel Iterators ===\n");
+
+    // Test 9: The same chain as Test 4, in parallel
+    println!("Test 9: par_iter().map().filter().collect()");
+    let data = vec![1, 2, 3, 4, 5];
+    let result: Vec<i32> = data.par_iter()
+        .map(|x| x * 2)
+        .filter(|x| *x > 5)
+        .collect();
+    println!("Output: {:?}", result);
+    println!("Expected: [6, 8, 10] (same order as the sequential version)\n");
+
+    // Test 10: sum over a big range, checked against the sequential chain
+    println!("Test 10: sum");
+    let data: Vec<u64> = (1..=100_000).collect();
+    let parallel: u64 = data.par_iter().map(|x| x * x).sum();
+    let sequential: u64 = data.clone().my_iter().my_map(|x| x * x).sum();
+    println!("Parallel sum of squares:   {}", parallel);
+    println!("Sequential sum of squares: {}\n", sequential);
+
+    // Test 11: reduce with a non-commutative op - order is kept
+    println!("Test 11: reduce (string concatenation)");
+    let words = ["map", "filter", "reduce", "in", "parallel"];
+    let sentence = words
+        .par_iter()
+        .map(|w| w.to_uppercase())
+        .reduce(String::new, |a, b| if a.is_empty() { b } else { a + " " + &b });
+    println!("Output: {}", sentence);
+    println!("Expected: MAP FILTER REDUCE IN PARALLEL\n");
+
+    // Test 12: flat_map
+    println!("Test 12: flat_map");
+    let data = vec![1, 2, 3];
+    let result: Vec<i32> = data.par_iter().flat_map(|&x| vec![x; x as usize]).collect();
+    println!("Output: {:?}", result);
+    println!("Expected: [1, 2, 2, 3, 3, 3]\n");
+
+    // Test 13: for_each runs on several threads
+    println!("Test 13: for_each");
+    let data: Vec<u32> = (0..10_000).collect();
+    let evens = AtomicUsize::new(0);
+    data.par_iter().for_each(|x| {
+        if x % 2 == 0 {
+            evens.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    println!("Even numbers counted: {}", evens.load(Ordering::Relaxed));
+    println!("Expected: 5000\n");
+
+    // Test 14: empty input
+    println!("Test 14: empty input");
+    let empty: Vec<i32> = Vec::new();
+    let total: i32 = empty.par_iter().sum();
+    let product = empty.par_iter().map(|x| *x).reduce(|| 1, |a, b| a * b);
+    println!("Sum: {}, product: {}", total, product);
+    println!("Expected: Sum: 0, product: 1");
 }